@@ -1,5 +1,6 @@
 
 use crate::intcode::*;
+use crate::intcode::memory::Memory;
 
 #[allow(unused_imports)]
 use aoch::{AoCDay, DayPart, daystr, run_test, test_runner};
@@ -64,7 +65,7 @@ fn few_steps() {
 	run_test(|ram| {
 		let mut ic = Intcode::new(ram.to_vec());
 		assert_eq!(ic.run(), RunResult::Halted);
-		(ic.pc(), ic.stepped(), ic.ram)
+		(ic.pc(), ic.stepped(), ic.ram.dense().to_vec())
 	}, &cases);
 }
 
@@ -16,15 +16,15 @@ impl AoCDay for Day05 {
 	}
 	fn part1(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
 		let mut cpu = _data.clone();
-		cpu.input.push(1);
+		cpu.input.push_back(1);
 		assert_eq!(cpu.run(), RunResult::Halted);
-		cpu.output.pop().unwrap()
+		cpu.output.pop_back().unwrap()
 	}
 	fn part2(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
 		let mut cpu = _data.clone();
-		cpu.input.push(5);
+		cpu.input.push_back(5);
 		assert_eq!(cpu.run(), RunResult::Halted);
-		cpu.output.pop().expect("CPU did not output any values")
+		cpu.output.pop_back().expect("CPU did not output any values")
 	}
 }
 
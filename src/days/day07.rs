@@ -4,63 +4,31 @@ use itertools::Itertools;
 #[allow(unused_imports)]
 use aoch::{AoCDay, DayPart, daystr, run_test, test_runner};
 
-use crate::intcode::{Intcode, ICInt, RunResult};
+use crate::intcode::{Intcode, ICInt};
+use crate::intcode::scheduler::{Outcome, Scheduler};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Day07;
 
-fn run_amps(program: Intcode, initial: ICInt, phases: &[ICInt]) -> ICInt {
-	// let mut next_input = initial;
-	let mut cpus: Vec<_> = phases.iter()
+/// Runs a chain of amplifiers, feeding the last amplifier's output back into the first.
+/// Returns the last signal emitted, or why the amplifiers stopped without all halting.
+fn run_amps(program: Intcode, initial: ICInt, phases: &[ICInt]) -> Result<ICInt, Outcome> {
+	assert!(phases.len() > 1);
+	let cpus = phases.iter()
 		.map(|p| {
 			let mut ic = program.clone();
-			ic.input.push(*p);
+			ic.input.push_back(*p);
 			ic
 		})
 		.collect();
 
-	let cpu_count = cpus.len();
-	assert!(cpu_count > 1);
-
-	cpus[0].input.push(initial);
-	while ! cpus[cpu_count-1].is_halted() {
-		// eprintln!("looping main... {:?}", cpus);
-		for i in 0..cpu_count {
-			let prev_ind = if i == 0 { cpu_count-1 } else { i-1 };
-			while cpus[prev_ind].output.len() > 0 {
-				let f = cpus[prev_ind].output.remove(0);
-				// eprintln!("[{} -> {}] signal={}", prev_ind, i, f);
-				cpus[i].input.push(f);
-			}
-
-			let result = cpus[i].run();
-			// eprintln!("[{}] RunResult::{:?} -- (inputs={:?}, outputs={:?})", i, result, cpus[i].input, cpus[i].output);
-			match result {
-				RunResult::Halted | RunResult::Starved => {},
-				_ => panic!("execution error: {:?}", result),
-			}
-			// while cpus[i].output.len() > 0 {
-			// 	let f = cpus[i].output.remove(0);
-			// 	let next_ind = (i+1) % cpu_count;
-			// 	eprintln!("[{} -> {}] signal={}", i, next_ind, f);
-			// 	cpus[next_ind].input.push(f);
-			// }
-		}
+	let mut sched = Scheduler::ring(cpus);
+	sched.machines[0].input.push_back(initial);
+	match sched.run() {
+		// at end of execution, the last CPU's output has been sent back to the first one
+		Outcome::Halted => Ok(sched.machines[0].input.pop_back().expect("last CPU halted without output")),
+		outcome => Err(outcome),
 	}
-
-	// at end of execution, each CPUs otuput is sent to the next one
-	// cpus[0].input.pop().unwrap();
-	cpus[cpu_count - 1].output.pop().expect("last CPU halted without output")
-	// for (i, p) in phases.iter().enumerate() {
-	// 	println!("running phase {} with setting {} and input {}", i, p, next_input);
-	// 	program.reset();
-	// 	program.input = vec![*p, next_input];
-	// 	program.run();
-	// 	assert_eq!(program.output.len(), 1);
-	// 	next_input = program.output.pop().unwrap();
-	// }
-	// println!("finished with output {}", next_input);
-	// next_input
 }
 
 impl AoCDay for Day07 {
@@ -75,7 +43,8 @@ impl AoCDay for Day07 {
 		PHASES.iter().copied()
 			.permutations(5)
 			.map(|p| {
-				let out = run_amps(_data.clone(), 0, p.as_slice());
+				let out = run_amps(_data.clone(), 0, p.as_slice())
+					.unwrap_or_else(|rr| panic!("amplifiers stopped with phases {:?}: {:?}", p, rr));
 				(p, out)
 			})
 			.max_by_key(|(p, out)| *out)
@@ -87,7 +56,8 @@ impl AoCDay for Day07 {
 		PHASES.iter().copied()
 			.permutations(5)
 			.map(|p| {
-				let out = run_amps(_data.clone(), 0, p.as_slice());
+				let out = run_amps(_data.clone(), 0, p.as_slice())
+					.unwrap_or_else(|rr| panic!("amplifiers stopped with phases {:?}: {:?}", p, rr));
 				(p, out)
 			})
 			.max_by_key(|(p, out)| *out)
@@ -106,10 +76,26 @@ fn amp_phases() {
 			33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0]), 65210),
 	];
 	run_test(|(phases, prog)| {
-		run_amps(Intcode::new(prog.to_vec()), 0, phases)
+		run_amps(Intcode::new(prog.to_vec()), 0, phases).unwrap()
 	}, &cases);
 }
 
+#[test]
+fn amp_fault() {
+	use crate::intcode::{Fault, RunResult};
+
+	// passes the signal through, unless the phase is 1 - then jumps to a negative address
+	let prog = Intcode::new(vec![3,17,3,18,1008,17,1,19,1005,19,14,4,18,99,1105,1,-1,0,0,0]);
+	assert_eq!(run_amps(prog.clone(), 7, &[0, 2]), Ok(7));
+	assert_eq!(
+		run_amps(prog, 7, &[0, 1]),
+		Err(Outcome::Fault {
+			machine: 1,
+			result: RunResult::Fault(Fault::NegativeJump { pc: 14, target: -1 }),
+		}),
+	);
+}
+
 #[test]
 fn part1() {
 	let cases = [
@@ -16,17 +16,17 @@ impl AoCDay for Day09 {
 	}
 	fn part1(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
 		_data.reset();
-		_data.input.push(1);
+		_data.input.push_back(1);
 		assert_eq!(_data.run(), RunResult::Halted);
 		assert_eq!(_data.output.len(), 1);
-		_data.output.pop().unwrap()
+		_data.output.pop_back().unwrap()
 	}
 	fn part2(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
 		_data.reset();
-		_data.input.push(2);
+		_data.input.push_back(2);
 		assert_eq!(_data.run(), RunResult::Halted);
 		assert_eq!(_data.output.len(), 1);
-		_data.output.pop().unwrap()
+		_data.output.pop_back().unwrap()
 	}
 }
 
@@ -5,6 +5,7 @@ use std::collections::BTreeMap;
 use aoch::{AoCDay, DayPart, daystr, run_test, test_runner};
 
 use crate::intcode::{Intcode, RunResult, ICInt};
+use crate::intcode::io::IntcodeIo;
 use crate::rendering;
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -48,74 +49,113 @@ impl Direction {
 	}
 }
 
+/// The painting robot, which is driven directly by the program's input and output
 #[derive(Debug)]
-pub struct Mapper {
-	prog: Intcode,
+struct Robot {
 	pos: (isize, isize),
 	dir: Direction,
-	map: BTreeMap<(isize, isize), Color>
+	map: BTreeMap<(isize, isize), Color>,
+	/// A color the program has output, which is applied along with the following turn
+	paint: Option<Color>,
+	/// The first bad instruction the program gave, after which the robot stops responding
+	error: Option<PaintError>,
+}
+impl IntcodeIo for Robot {
+	fn read(&mut self) -> Option<ICInt> {
+		if self.error.is_some() {
+			// starve the program, so that it stops
+			return None;
+		}
+		Some(*self.map.get(&self.pos).unwrap_or(&Color::Black) as ICInt)
+	}
+	fn write(&mut self, value: ICInt) {
+		if self.error.is_some() {
+			return;
+		}
+		let col = match self.paint.take() {
+			None => {
+				self.paint = match value {
+					0 => Some(Color::Black),
+					1 => Some(Color::White),
+					_ => {
+						self.error = Some(PaintError::BadColor(value));
+						None
+					},
+				};
+				return;
+			},
+			Some(col) => col,
+		};
+
+		self.map.insert(self.pos, col);
+		match value {
+			0 => self.dir = self.dir.turn_left(),
+			1 => self.dir = self.dir.turn_right(),
+			_ => {
+				self.error = Some(PaintError::BadTurn(value));
+				return;
+			},
+		}
+		self.dir.advance(&mut self.pos);
+	}
+}
+
+/// Why the painting program didn't finish painting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaintError {
+	/// The program stopped for a reason other than halting
+	Stopped(RunResult),
+	/// The program output a color code other than 0 or 1
+	BadColor(ICInt),
+	/// The program output a turn code other than 0 or 1
+	BadTurn(ICInt),
+	/// The program halted after a color without the turn that goes with it
+	Unpaired,
+}
+
+#[derive(Debug)]
+pub struct Mapper {
+	prog: Intcode,
+	robot: Robot,
 }
 impl Mapper {
 	pub fn new(prog: Intcode) -> Mapper {
 		Mapper {
 			prog,
-			pos: (0, 0),
-			dir: Direction::Up,
-			map: BTreeMap::new(),
+			robot: Robot {
+				pos: (0, 0),
+				dir: Direction::Up,
+				map: BTreeMap::new(),
+				paint: None,
+				error: None,
+			},
 		}
 	}
-	fn run(&mut self) {
-		loop {
-			match self.prog.run() {
-				bad @ (RunResult::RepeatedState | RunResult::InvalidInstruction(_)) => panic!("{:?}", bad),
-				RunResult::Halted => {
-					self.process_output();
-					break;
-				},
-				RunResult::Starved => {
-					self.process_output();
-					self.prog.input.push(
-						*self.map.get(&self.pos)
-							.unwrap_or(&Color::Black)
-							as ICInt
-					);
-
-				}
-			}
+	/// Runs the painting program to completion, or returns why it stopped unexpectedly.
+	fn run(&mut self) -> Result<(), PaintError> {
+		let rr = self.prog.run_io(&mut self.robot);
+		if let Some(e) = self.robot.error.take() {
+			return Err(e);
 		}
-	}
-	fn process_output(&mut self) {
-		assert!(self.prog.output.len() % 2 == 0, "program did not output instruction pairs");
-		for [col, dir] in self.prog.output.array_windows().copied() {
-			let tile_color = self.map.entry(self.pos).or_default();
-
-			match col {
-				0 => *tile_color = Color::Black,
-				1 => *tile_color = Color::White,
-				_ => panic!("unknown color code: {:?}", col),
-			}
-			match dir {
-				0 => self.dir = self.dir.turn_left(),
-				1 => self.dir = self.dir.turn_right(),
-				_ => panic!("unknown direction code: {:?}", dir),
-			}
-
-			self.dir.advance(&mut self.pos);
+		match rr {
+			RunResult::Halted if self.robot.paint.is_some() => Err(PaintError::Unpaired),
+			RunResult::Halted => Ok(()),
+			bad => Err(PaintError::Stopped(bad)),
 		}
-
-		self.prog.output.clear();
 	}
 	fn reset(&mut self, starting_color: Color) {
 		self.prog.reset();
-		self.map.clear();
-		self.pos = (0, 0);
-		self.dir = Direction::Up;
+		self.robot.map.clear();
+		self.robot.pos = (0, 0);
+		self.robot.dir = Direction::Up;
+		self.robot.paint = None;
+		self.robot.error = None;
 
-		self.map.insert((0, 0), starting_color);
+		self.robot.map.insert((0, 0), starting_color);
 	}
 	fn render(&self, unset: char, set: char, newline: bool) -> String {
 		let (xmin, xmax, ymin, ymax) = {
-			let mut coords = self.map.iter()
+			let mut coords = self.robot.map.iter()
 				.filter(|&(_, c)| *c == Color::White)
 				.map(|(k, _)| *k);
 			let first = coords.next().unwrap();
@@ -140,7 +180,7 @@ impl Mapper {
 
 		for y in (ymin..=ymax).rev() {
 			for x in xmin..=xmax {
-				let color = self.map.get(&(x, y)).copied().unwrap_or_default();
+				let color = self.robot.map.get(&(x, y)).copied().unwrap_or_default();
 				canvas.push(match color {
 					Color::Black => unset,
 					Color::White => set,
@@ -165,17 +205,44 @@ impl AoCDay for Day11 {
 	}
 	fn part1(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
 		_data.reset(Color::Black);
-		_data.run();
-		_data.map.len().to_string()
+		_data.run().expect("painting program stopped unexpectedly");
+		_data.robot.map.len().to_string()
 	}
 	fn part2(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
 		_data.reset(Color::White);
-		_data.run();
+		_data.run().expect("painting program stopped unexpectedly");
 		eprintln!("{}", _data.render(' ', '#', true));
 		rendering::parse(_data.render('0', '1', false))
 	}
 }
 
+#[test]
+fn painter_fault() {
+	use crate::intcode::Fault;
+
+	// reads the panel color, then writes it back through an immediate parameter
+	let mut mapper = Mapper::new(Intcode::new(vec![3,9,1101,0,0,9,11101,0,0,0,99]));
+	mapper.reset(Color::White);
+	assert_eq!(mapper.run(), Err(PaintError::Stopped(RunResult::Fault(Fault::ImmediateWrite { pc: 6 }))));
+}
+
+#[test]
+fn bad_instructions() {
+	let cases: [(&[ICInt], _); 3] = [
+		// paints with color 5
+		(&[104,5,104,0,99], PaintError::BadColor(5)),
+		// paints white, then turns with code -1
+		(&[104,1,104,-1,99], PaintError::BadTurn(-1)),
+		// paints white, then halts before turning
+		(&[104,1,99], PaintError::Unpaired),
+	];
+	for (prog, err) in cases {
+		let mut mapper = Mapper::new(Intcode::new(prog.to_vec()));
+		mapper.reset(Color::Black);
+		assert_eq!(mapper.run(), Err(err));
+	}
+}
+
 #[test]
 fn part1() {
 	let cases = [
@@ -1,33 +1,366 @@
 
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::Write;
+use std::time::Duration;
+
 #[allow(unused_imports)]
 use aoch::{AoCDay, DayPart, daystr, run_test, test_runner};
 
+use crate::intcode::{Intcode, RunResult, ICInt};
+use crate::intcode::io::IntcodeIo;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Tile {
+	#[default]
+	Empty = 0,
+	Wall = 1,
+	Block = 2,
+	Paddle = 3,
+	Ball = 4,
+}
+impl Tile {
+	fn from_code(code: ICInt) -> Option<Tile> {
+		Some(match code {
+			0 => Tile::Empty,
+			1 => Tile::Wall,
+			2 => Tile::Block,
+			3 => Tile::Paddle,
+			4 => Tile::Ball,
+			_ => return None,
+		})
+	}
+	fn symbol(&self) -> char {
+		match self {
+			Tile::Empty => ' ',
+			Tile::Wall => '#',
+			Tile::Block => '=',
+			Tile::Paddle => '-',
+			Tile::Ball => 'o',
+		}
+	}
+}
+
+/// What the arcade cabinet is currently showing
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Screen {
+	/// Rows of tiles, grown as the program draws further out
+	tiles: Vec<Vec<Tile>>,
+	pub score: ICInt,
+}
+impl Screen {
+	fn draw(&mut self, x: usize, y: usize, tile: Tile) {
+		if self.tiles.len() <= y {
+			self.tiles.resize(y + 1, Vec::new());
+		}
+		let row = &mut self.tiles[y];
+		if row.len() <= x {
+			row.resize(x + 1, Tile::Empty);
+		}
+		row[x] = tile;
+	}
+	pub fn count(&self, tile: Tile) -> usize {
+		self.tiles.iter().flatten().filter(|&&t| t == tile).count()
+	}
+	/// The screen and score as a frame of terminal animation, drawn over the previous frame
+	/// from the top left corner
+	pub fn frame(&self) -> String {
+		// clearing the rest of each line removes anything left from a longer line before
+		let mut frame = String::from("\x1b[H");
+		for line in self.to_string().lines() {
+			frame.push_str(line);
+			frame.push_str("\x1b[K\n");
+		}
+		frame.push_str(&format!("Score: {}\x1b[K\n", self.score));
+		frame
+	}
+}
+impl fmt::Display for Screen {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for row in &self.tiles {
+			let line: String = row.iter().map(Tile::symbol).collect();
+			writeln!(f, "{}", line.trim_end())?;
+		}
+		Ok(())
+	}
+}
+
+/// Shows the game on a terminal as it's played
+pub struct Animation {
+	out: Box<dyn Write>,
+	/// Pause after each frame
+	delay: Duration,
+	frames: usize,
+	/// Whether the cursor has been hidden, and so needs showing again
+	hidden: bool,
+}
+impl Animation {
+	fn show(&mut self, screen: &Screen) {
+		let mut frame = screen.frame();
+		if self.frames == 0 {
+			// clear the terminal and hide the cursor
+			frame.insert_str(0, "\x1b[2J\x1b[?25l");
+			self.hidden = true;
+		}
+		self.out.write_all(frame.as_bytes())
+			.and_then(|_| self.out.flush())
+			.expect("unable to draw arcade screen");
+		self.frames += 1;
+		std::thread::sleep(self.delay);
+	}
+	fn finish(&mut self) -> std::io::Result<()> {
+		if std::mem::replace(&mut self.hidden, false) {
+			self.out.write_all(b"\x1b[?25h")?;
+		}
+		self.out.flush()
+	}
+}
+impl Drop for Animation {
+	/// Gives the terminal its cursor back, even if the game panicked
+	fn drop(&mut self) {
+		let _ = self.finish();
+	}
+}
+impl fmt::Debug for Animation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Animation")
+			.field("delay", &self.delay)
+			.field("frames", &self.frames)
+			.finish_non_exhaustive()
+	}
+}
+
+/// The arcade cabinet, which draws the program's output and moves the joystick to follow
+/// the ball whenever the program reads it
+#[derive(Debug, Default)]
+pub struct Arcade {
+	pub screen: Screen,
+	/// Values of an output triple still being received
+	pending: Vec<ICInt>,
+	ball: Option<usize>,
+	paddle: Option<usize>,
+	animation: Option<Animation>,
+	/// The first bad output from the program, after which the cabinet stops responding
+	error: Option<ArcadeError>,
+}
+impl IntcodeIo for Arcade {
+	fn read(&mut self) -> Option<ICInt> {
+		if self.error.is_some() {
+			// starve the program, so that it stops
+			return None;
+		}
+		let (ball, paddle) = self.ball.zip(self.paddle)?;
+		// the game reads the joystick once per tick, after drawing everything that moved
+		if let Some(anim) = &mut self.animation {
+			anim.show(&self.screen);
+		}
+		Some(match ball.cmp(&paddle) {
+			Ordering::Less => -1,
+			Ordering::Equal => 0,
+			Ordering::Greater => 1,
+		})
+	}
+	fn write(&mut self, value: ICInt) {
+		if self.error.is_some() {
+			return;
+		}
+		self.pending.push(value);
+		if self.pending.len() < 3 {
+			return;
+		}
+		match self.pending[..] {
+			[-1, 0, score] => self.screen.score = score,
+			[x, y, code] => match (usize::try_from(x), usize::try_from(y), Tile::from_code(code)) {
+				(_, _, None) => self.error = Some(ArcadeError::UnknownTile(code)),
+				(Err(_), _, _) | (_, Err(_), _) => self.error = Some(ArcadeError::OffScreen { x, y }),
+				(Ok(x), Ok(y), Some(tile)) => {
+					match tile {
+						Tile::Ball => self.ball = Some(x),
+						Tile::Paddle => self.paddle = Some(x),
+						_ => {},
+					}
+					self.screen.draw(x, y, tile);
+				},
+			},
+			_ => unreachable!(),
+		}
+		self.pending.clear();
+	}
+}
+/// Why the game program didn't play to the end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArcadeError {
+	/// The program stopped for a reason other than halting
+	Stopped(RunResult),
+	/// The program drew a tile with an id other than 0 to 4
+	UnknownTile(ICInt),
+	/// The program drew a tile at a negative position
+	OffScreen { x: ICInt, y: ICInt },
+	/// The program halted partway through outputting a tile
+	Incomplete,
+}
+
+impl Arcade {
+	/// Animates the game on `out`, pausing for `delay` between frames
+	pub fn with_animation<W: Write + 'static>(mut self, out: W, delay: Duration) -> Arcade {
+		self.animation = Some(Animation { out: Box::new(out), delay, frames: 0, hidden: false });
+		self
+	}
+
+	/// Runs the game program to completion, or returns why it stopped unexpectedly.
+	pub fn play(&mut self, prog: &mut Intcode) -> Result<(), ArcadeError> {
+		let rr = prog.run_io(&mut *self);
+		if let Some(anim) = &mut self.animation {
+			// the final frame, with the score once the last block is gone
+			anim.show(&self.screen);
+			anim.finish().expect("unable to draw arcade screen");
+		}
+		if let Some(e) = self.error.take() {
+			return Err(e);
+		}
+		match rr {
+			RunResult::Halted if !self.pending.is_empty() => Err(ArcadeError::Incomplete),
+			RunResult::Halted => Ok(()),
+			bad => Err(ArcadeError::Stopped(bad)),
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Day13;
 
 impl AoCDay for Day13 {
-	type Data<'i> = Vec<u8>;
-	type Answer = usize;
+	type Data<'i> = Intcode;
+	type Answer = ICInt;
 	fn day(&self) -> u8 { 13 }
 	fn parse<'i>(&self, input: &'i str) -> Self::Data<'i> {
-		input.split('\n')
-			.filter_map(aoch::parsing::trimmed)
-			.map(|n| n.parse::<u8>().unwrap())
-			.collect()
+		Intcode::parse(input)
 	}
 	fn part1(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
-		todo!("Day {} Part 1", Self::day(&Self));
+		let mut arcade = Arcade::default();
+		arcade.play(&mut _data.clone()).expect("game stopped unexpectedly");
+		arcade.screen.count(Tile::Block) as ICInt
 	}
 	fn part2(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
-		todo!("Day {} Part 2", Self::day(&Self));
+		let mut prog = _data.clone();
+		// insert quarters
+		prog.ram[0] = 2;
+		let mut arcade = Arcade::default();
+		arcade.play(&mut prog).expect("game stopped unexpectedly");
+		assert_eq!(arcade.screen.count(Tile::Block), 0, "game ended with blocks left");
+		arcade.screen.score
+	}
+}
+
+#[test]
+fn cabinet() {
+	let mut arcade = Arcade::default();
+	assert_eq!(arcade.read(), None, "joystick is idle until the ball and paddle are drawn");
+	for v in [0,0,1, 1,0,1, 2,0,1, 3,0,1, 0,1,1, 1,1,2, 3,1,1, 0,2,1, 2,2,4, 3,2,1, 0,3,1, 1,3,3] {
+		arcade.write(v);
+	}
+	assert_eq!(arcade.read(), Some(1));
+	for v in [-1,0,12345, 1,3,0, 2,3,3] {
+		arcade.write(v);
+	}
+	assert_eq!(arcade.read(), Some(0));
+	assert_eq!(arcade.screen.score, 12345);
+	assert_eq!(arcade.screen.count(Tile::Block), 1);
+	assert_eq!(arcade.screen.to_string(), "####\n#= #\n# o#\n# -\n");
+	assert_eq!(arcade.screen.frame(), concat!(
+		"\x1b[H",
+		"####\x1b[K\n",
+		"#= #\x1b[K\n",
+		"# o#\x1b[K\n",
+		"# -\x1b[K\n",
+		"Score: 12345\x1b[K\n",
+	));
+}
+
+#[test]
+fn bad_output() {
+	let cases: [(&[ICInt], _); 4] = [
+		// draws tile 7 at (1, 1)
+		(&[104,1,104,1,104,7,99], ArcadeError::UnknownTile(7)),
+		// draws a wall at (-1, 2)
+		(&[104,-1,104,2,104,1,99], ArcadeError::OffScreen { x: -1, y: 2 }),
+		// draws a wall at (0, 0), then halts before drawing the ball
+		(&[104,0,104,0,104,1,104,1,99], ArcadeError::Incomplete),
+		// waits for the joystick before anything is drawn
+		(&[3,0,99], ArcadeError::Stopped(RunResult::Starved)),
+	];
+	for (prog, err) in cases {
+		let mut arcade = Arcade::default();
+		assert_eq!(arcade.play(&mut Intcode::new(prog.to_vec())), Err(err));
+	}
+}
+
+#[test]
+fn animation() {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	/// A terminal that can still be read once the arcade owns it
+	#[derive(Clone, Default)]
+	struct Shared(Rc<RefCell<Vec<u8>>>);
+	impl Write for Shared {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			self.0.borrow_mut().write(buf)
+		}
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+	let drawn = |term: &Shared| String::from_utf8(term.0.borrow().clone()).unwrap();
+
+	// draws the paddle and ball, then scores between two ticks
+	let mut prog = Intcode::assemble("
+		out #0
+		out #0
+		out #3
+		out #1
+		out #0
+		out #4
+		inp [j]
+		out #-1
+		out #0
+		out #7
+		inp [j]
+		hlt
+	j:	db 0
+	").unwrap();
+	let term = Shared::default();
+	let mut arcade = Arcade::default().with_animation(term.clone(), Duration::ZERO);
+	assert_eq!(arcade.play(&mut prog), Ok(()));
+	assert_eq!(drawn(&term), concat!(
+		"\x1b[2J\x1b[?25l",
+		"\x1b[H-o\x1b[K\nScore: 0\x1b[K\n",
+		"\x1b[H-o\x1b[K\nScore: 7\x1b[K\n",
+		// the final frame
+		"\x1b[H-o\x1b[K\nScore: 7\x1b[K\n",
+		"\x1b[?25h",
+	));
+	drop(arcade);
+	assert_eq!(drawn(&term).matches("\x1b[?25h").count(), 1, "cursor is only shown once");
+
+	// a game abandoned partway through still gives the cursor back
+	let term = Shared::default();
+	let mut arcade = Arcade::default().with_animation(term.clone(), Duration::ZERO);
+	for v in [0,0,3, 1,0,4] {
+		arcade.write(v);
 	}
+	assert_eq!(arcade.read(), Some(1));
+	assert!(drawn(&term).starts_with("\x1b[2J\x1b[?25l\x1b[H"));
+	assert!(!drawn(&term).contains("\x1b[?25h"));
+	drop(arcade);
+	assert!(drawn(&term).ends_with("\x1b[?25h"));
 }
 
 #[test]
 fn part1() {
 	let cases = [
 		// (TEST_INPUT, 0),
-		(daystr!("13"), 0),
+		(daystr!("13"), 318),
 	];
 	test_runner::<_, _>(Day13, DayPart::Part1, &cases);
 }
@@ -35,7 +368,7 @@ fn part1() {
 fn part2() {
 	let cases = [
 		// (TEST_INPUT, 0),
-		(daystr!("13"), 0),
+		(daystr!("13"), 16309),
 	];
 	test_runner::<_, _>(Day13, DayPart::Part2, &cases);
 }
@@ -1,33 +1,318 @@
 
+use std::fmt;
+
 #[allow(unused_imports)]
 use aoch::{AoCDay, DayPart, daystr, run_test, test_runner};
+use itertools::Itertools;
+
+use crate::intcode::{Intcode, RunResult, ICInt};
+use crate::intcode::ascii::AsciiConsole;
+
+/// Longest routine the robot's memory accepts, in characters
+const MAX_ROUTINE: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dir {
+	Up,
+	Right,
+	Down,
+	Left,
+}
+impl Dir {
+	fn turn_left(&self) -> Dir {
+		match self {
+			Dir::Up => Dir::Left,
+			Dir::Left => Dir::Down,
+			Dir::Down => Dir::Right,
+			Dir::Right => Dir::Up,
+		}
+	}
+	fn turn_right(&self) -> Dir {
+		self.turn_left().turn_left().turn_left()
+	}
+	fn step(&self, (x, y): (isize, isize)) -> (isize, isize) {
+		match self {
+			Dir::Up => (x, y - 1),
+			Dir::Down => (x, y + 1),
+			Dir::Left => (x - 1, y),
+			Dir::Right => (x + 1, y),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Turn {
+	Left,
+	Right,
+}
+
+/// A turn followed by a number of steps forward. Only the first move of a path can go
+/// straight ahead without turning, if the robot starts out facing along the scaffold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+	pub turn: Option<Turn>,
+	pub steps: usize,
+}
+impl fmt::Display for Move {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.turn {
+			Some(Turn::Left) => write!(f, "L,")?,
+			Some(Turn::Right) => write!(f, "R,")?,
+			None => {},
+		}
+		write!(f, "{}", self.steps)
+	}
+}
+
+/// The scaffolding as seen by the camera
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scaffold {
+	grid: Vec<Vec<bool>>,
+	/// Where the robot is, and which way it faces
+	robot: ((isize, isize), Dir),
+}
+impl Scaffold {
+	pub fn parse(view: &str) -> Scaffold {
+		let mut robot = None;
+		let grid = view.lines()
+			.filter(|l| !l.is_empty())
+			.enumerate()
+			.map(|(y, line)| line.chars().enumerate().map(|(x, c)| {
+				let dir = match c {
+					'#' => return true,
+					'.' | 'X' => return false,
+					'^' => Dir::Up,
+					'v' => Dir::Down,
+					'<' => Dir::Left,
+					'>' => Dir::Right,
+					_ => panic!("unknown camera pixel: {:?}", c),
+				};
+				robot = Some(((x as isize, y as isize), dir));
+				true
+			}).collect())
+			.collect();
+		Scaffold { grid, robot: robot.expect("camera did not show the robot on the scaffold") }
+	}
+
+	fn is_scaffold(&self, (x, y): (isize, isize)) -> bool {
+		let row = usize::try_from(y).ok().and_then(|y| self.grid.get(y));
+		let cell = usize::try_from(x).ok().and_then(|x| row?.get(x));
+		cell.copied().unwrap_or(false)
+	}
+
+	/// Positions where the scaffold crosses itself
+	pub fn intersections(&self) -> Vec<(usize, usize)> {
+		let mut found = Vec::new();
+		for (y, row) in self.grid.iter().enumerate() {
+			for x in (0..row.len()).filter(|&x| row[x]) {
+				let pos = (x as isize, y as isize);
+				if [Dir::Up, Dir::Down, Dir::Left, Dir::Right].iter().all(|d| self.is_scaffold(d.step(pos))) {
+					found.push((x, y));
+				}
+			}
+		}
+		found
+	}
+	/// The sum of the alignment parameters of every intersection
+	pub fn alignment(&self) -> usize {
+		self.intersections().iter().map(|(x, y)| x * y).sum()
+	}
+
+	/// Follows the scaffold from the robot to its end, going straight over every intersection
+	pub fn path(&self) -> Vec<Move> {
+		let (mut pos, mut dir) = self.robot;
+		let mut moves = Vec::new();
+		loop {
+			let turn = if moves.is_empty() && self.is_scaffold(dir.step(pos)) {
+				None
+			} else if self.is_scaffold(dir.turn_left().step(pos)) {
+				dir = dir.turn_left();
+				Some(Turn::Left)
+			} else if self.is_scaffold(dir.turn_right().step(pos)) {
+				dir = dir.turn_right();
+				Some(Turn::Right)
+			} else {
+				return moves;
+			};
+			let mut steps = 0;
+			while self.is_scaffold(dir.step(pos)) {
+				pos = dir.step(pos);
+				steps += 1;
+			}
+			moves.push(Move { turn, steps });
+		}
+	}
+}
+
+/// A sequence split into a main routine that calls a few functions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression<T> {
+	/// Indices into `functions`, in the order they are called
+	pub main: Vec<usize>,
+	pub functions: Vec<Vec<T>>,
+}
+impl<T: Clone + fmt::Display> Compression<T> {
+	/// The sequence that running the main routine produces
+	pub fn expand(&self) -> Vec<T> {
+		self.main.iter().flat_map(|&f| self.functions[f].iter().cloned()).collect()
+	}
+	/// The main routine and each function as text, calling the functions `A`, `B`, ..
+	pub fn routines(&self) -> Vec<String> {
+		let main = self.main.iter().map(|&f| char::from(b'A' + f as u8)).join(",");
+		std::iter::once(main)
+			.chain(self.functions.iter().map(|f| f.iter().join(",")))
+			.collect()
+	}
+}
+
+/// Length of items written out separated by commas
+fn joined_len<T: fmt::Display>(items: &[T]) -> usize {
+	items.iter().map(|t| t.to_string().len() + 1).sum::<usize>().saturating_sub(1)
+}
+
+/// Splits `items` into calls to at most `functions` functions, such that the main routine and
+/// every function fit in `max_len` characters once written out separated by commas.
+///
+/// Functions are found in the order they're first called: at each point in the sequence, any
+/// function that matches is tried first, then every prefix that fits as a new function.
+pub fn compress<T: Clone + PartialEq + fmt::Display>(items: &[T], functions: usize, max_len: usize) -> Option<Compression<T>> {
+	fn search<T: Clone + PartialEq + fmt::Display>(items: &[T], at: usize, functions: usize, max_len: usize, found: &mut Compression<T>) -> bool {
+		if at == items.len() {
+			return true;
+		}
+		// every call takes a letter and a comma
+		if (found.main.len() + 1) * 2 - 1 > max_len {
+			return false;
+		}
+		for f in 0..found.functions.len() {
+			if items[at..].starts_with(&found.functions[f]) {
+				found.main.push(f);
+				if search(items, at + found.functions[f].len(), functions, max_len, found) {
+					return true;
+				}
+				found.main.pop();
+			}
+		}
+		if found.functions.len() < functions {
+			let f = found.functions.len();
+			for end in at + 1..=items.len() {
+				if joined_len(&items[at..end]) > max_len {
+					break;
+				}
+				found.functions.push(items[at..end].to_vec());
+				found.main.push(f);
+				if search(items, end, functions, max_len, found) {
+					return true;
+				}
+				found.main.pop();
+				found.functions.pop();
+			}
+		}
+		false
+	}
+
+	let mut found = Compression { main: Vec::new(), functions: Vec::new() };
+	search(items, 0, functions, max_len, &mut found).then_some(found)
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct Day17;
 
 impl AoCDay for Day17 {
-	type Data<'i> = Vec<u8>;
-	type Answer = usize;
+	type Data<'i> = Intcode;
+	type Answer = ICInt;
 	fn day(&self) -> u8 { 17 }
 	fn parse<'i>(&self, input: &'i str) -> Self::Data<'i> {
-		input.split('\n')
-			.filter_map(aoch::parsing::trimmed)
-			.map(|n| n.parse::<u8>().unwrap())
-			.collect()
+		Intcode::parse(input)
 	}
 	fn part1(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
-		todo!("Day {} Part 1", Self::day(&Self));
+		let mut camera = AsciiConsole::new(_data.clone());
+		assert_eq!(camera.run(), RunResult::Halted);
+		Scaffold::parse(&camera.take_text()).alignment() as ICInt
 	}
 	fn part2(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
-		todo!("Day {} Part 2", Self::day(&Self));
+		let mut camera = AsciiConsole::new(_data.clone());
+		assert_eq!(camera.run(), RunResult::Halted);
+		let path = Scaffold::parse(&camera.take_text()).path();
+		let plan = compress(&path, 3, MAX_ROUTINE).expect("path does not fit in three functions");
+
+		let mut robot = _data.clone();
+		robot.ram[0] = 2;
+		let mut robot = AsciiConsole::new(robot);
+		for routine in plan.routines() {
+			robot.send_line(&routine);
+		}
+		// no continuous video feed
+		robot.send_line("n");
+		assert_eq!(robot.run(), RunResult::Halted);
+		*robot.values.last().expect("robot did not report the dust collected")
 	}
 }
 
+#[test]
+fn alignment() {
+	let view = "\
+..#..........
+..#..........
+#######...###
+#.#...#...#.#
+#############
+..#...#...#..
+..#####...^..
+";
+	let scaffold = Scaffold::parse(view);
+	assert_eq!(scaffold.intersections(), vec![(2, 2), (2, 4), (6, 4), (10, 4)]);
+	assert_eq!(scaffold.alignment(), 76);
+}
+
+#[test]
+fn path_and_compression() {
+	let view = "\
+#######...#####
+#.....#...#...#
+#.....#...#...#
+......#...#...#
+......#...###.#
+......#.....#.#
+^########...#.#
+......#.#...#.#
+......#########
+........#...#..
+....#########..
+....#...#......
+....#...#......
+....#...#......
+....#####......
+";
+	let path = Scaffold::parse(view).path();
+	assert_eq!(path.iter().join(","), "R,8,R,8,R,4,R,4,R,8,L,6,L,2,R,4,R,4,R,8,R,8,R,8,L,6,L,2");
+
+	// starting out facing along the scaffold
+	let view = "\
+#####
+....#
+....#
+....^
+";
+	assert_eq!(Scaffold::parse(view).path().iter().join(","), "3,L,4");
+
+	let plan = compress(&path, 3, MAX_ROUTINE).unwrap();
+	assert_eq!(plan.expand(), path);
+	assert_eq!(plan.functions.len(), 3);
+	assert!(plan.routines().iter().all(|r| r.len() <= MAX_ROUTINE), "{:?}", plan.routines());
+
+	// the compressor doesn't care what it's compressing
+	let plan = compress(&[1, 2, 3, 1, 2, 4, 3, 4], 3, 5).unwrap();
+	assert_eq!(plan.routines(), vec!["A,B,C", "1,2", "3,1,2", "4,3,4"]);
+	assert_eq!(compress(&[1, 2, 3, 1, 2, 4, 3, 4], 3, 3), None);
+	assert_eq!(compress(&[1, 2, 3, 4, 5], 2, 3), None);
+}
+
 #[test]
 fn part1() {
 	let cases = [
 		// (TEST_INPUT, 0),
-		(daystr!("17"), 0),
+		(daystr!("17"), 5620),
 	];
 	test_runner::<_, _>(Day17, DayPart::Part1, &cases);
 }
@@ -35,7 +320,7 @@ fn part1() {
 fn part2() {
 	let cases = [
 		// (TEST_INPUT, 0),
-		(daystr!("17"), 0),
+		(daystr!("17"), 768115),
 	];
 	test_runner::<_, _>(Day17, DayPart::Part2, &cases);
 }
@@ -1,33 +1,479 @@
 
+use std::collections::BTreeSet;
+use std::fmt;
+
 #[allow(unused_imports)]
 use aoch::{AoCDay, DayPart, daystr, run_test, test_runner};
+use itertools::Itertools;
+
+use crate::intcode::{Intcode, RunResult, ICInt};
+use crate::intcode::ascii::AsciiConsole;
+
+/// Most instructions the springdroid's memory can hold
+const MAX_INSTRUCTIONS: usize = 15;
+/// Most sensors read by each clause of a synthesized script
+const MAX_CLAUSE: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+	/// Sensors `A` to `D` are available
+	Walk,
+	/// Sensors `A` to `I` are available
+	Run,
+}
+impl Mode {
+	fn sensors(&self) -> u8 {
+		match self {
+			Mode::Walk => 4,
+			Mode::Run => 9,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reg {
+	/// True if there is ground this many tiles ahead, less one. `A` is `Sensor(0)`.
+	Sensor(u8),
+	T,
+	J,
+}
+impl Reg {
+	fn parse(s: &str) -> Option<Reg> {
+		match s {
+			"T" => Some(Reg::T),
+			"J" => Some(Reg::J),
+			s if s.len() == 1 && ("A"..="I").contains(&s) => Some(Reg::Sensor(s.as_bytes()[0] - b'A')),
+			_ => None,
+		}
+	}
+}
+impl fmt::Display for Reg {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Reg::Sensor(n) => write!(f, "{}", char::from(b'A' + n)),
+			Reg::T => write!(f, "T"),
+			Reg::J => write!(f, "J"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gate {
+	And,
+	Or,
+	Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Instr {
+	pub gate: Gate,
+	pub src: Reg,
+	/// Always `T` or `J`
+	pub dst: Reg,
+}
+impl fmt::Display for Instr {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let gate = match self.gate {
+			Gate::And => "AND",
+			Gate::Or => "OR",
+			Gate::Not => "NOT",
+		};
+		write!(f, "{} {} {}", gate, self.src, self.dst)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptErrorKind {
+	UnknownInstruction(String),
+	OperandCount(usize),
+	BadRegister(String),
+	/// Sensors can only be read
+	WriteToSensor(Reg),
+	/// A sensor beyond `D` was read in walk mode
+	OutOfRange(Reg),
+	TooLong(usize),
+	/// The script didn't end with `WALK` or `RUN`
+	MissingMode,
+	/// There was more after `WALK` or `RUN`
+	AfterMode,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+	pub line: usize,
+	pub kind: ScriptErrorKind,
+}
+impl fmt::Display for ScriptError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "line {}: ", self.line)?;
+		match &self.kind {
+			ScriptErrorKind::UnknownInstruction(i) => write!(f, "unknown instruction {:?}", i),
+			ScriptErrorKind::OperandCount(n) => write!(f, "instructions take 2 registers, found {}", n),
+			ScriptErrorKind::BadRegister(r) => write!(f, "unknown register {:?}", r),
+			ScriptErrorKind::WriteToSensor(r) => write!(f, "sensor {} cannot be written", r),
+			ScriptErrorKind::OutOfRange(r) => write!(f, "sensor {} can only be used when running", r),
+			ScriptErrorKind::TooLong(n) => write!(f, "{} instructions, but at most {} fit", n, MAX_INSTRUCTIONS),
+			ScriptErrorKind::MissingMode => write!(f, "script must end with WALK or RUN"),
+			ScriptErrorKind::AfterMode => write!(f, "nothing may follow WALK or RUN"),
+		}
+	}
+}
+impl std::error::Error for ScriptError {}
+
+/// A springscript program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+	pub instrs: Vec<Instr>,
+	pub mode: Mode,
+}
+impl Script {
+	/// Parses and validates a script, one instruction per line, ending with `WALK` or `RUN`
+	pub fn parse(src: &str) -> Result<Script, ScriptError> {
+		let mut instrs = Vec::new();
+		let mut mode = None;
+		let mut last = 0;
+		for (lineno, line) in src.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+			last = lineno + 1;
+			let err = |kind| ScriptError { line: lineno + 1, kind };
+			if mode.is_some() {
+				return Err(err(ScriptErrorKind::AfterMode));
+			}
+			let words: Vec<&str> = line.split_whitespace().collect();
+			let gate = match words[0] {
+				"WALK" | "RUN" if words.len() == 1 => {
+					mode = Some(if words[0] == "WALK" { Mode::Walk } else { Mode::Run });
+					continue;
+				},
+				"AND" => Gate::And,
+				"OR" => Gate::Or,
+				"NOT" => Gate::Not,
+				w => return Err(err(ScriptErrorKind::UnknownInstruction(w.to_owned()))),
+			};
+			if words.len() != 3 {
+				return Err(err(ScriptErrorKind::OperandCount(words.len() - 1)));
+			}
+			let reg = |w: &str| Reg::parse(w).ok_or_else(|| err(ScriptErrorKind::BadRegister(w.to_owned())));
+			let (src, dst) = (reg(words[1])?, reg(words[2])?);
+			if let Reg::Sensor(_) = dst {
+				return Err(err(ScriptErrorKind::WriteToSensor(dst)));
+			}
+			instrs.push((lineno + 1, Instr { gate, src, dst }));
+		}
+		let mode = mode.ok_or(ScriptError { line: last, kind: ScriptErrorKind::MissingMode })?;
+		if instrs.len() > MAX_INSTRUCTIONS {
+			return Err(ScriptError { line: instrs[MAX_INSTRUCTIONS].0, kind: ScriptErrorKind::TooLong(instrs.len()) });
+		}
+		for &(line, instr) in &instrs {
+			if let Reg::Sensor(n) = instr.src {
+				if n >= mode.sensors() {
+					return Err(ScriptError { line, kind: ScriptErrorKind::OutOfRange(instr.src) });
+				}
+			}
+		}
+		Ok(Script { instrs: instrs.into_iter().map(|(_, i)| i).collect(), mode })
+	}
+
+	/// Whether the droid jumps, given what its sensors see. Sensors beyond the end of
+	/// `ground` see ground.
+	pub fn jumps(&self, ground: &[bool]) -> bool {
+		let (mut t, mut j) = (false, false);
+		for instr in &self.instrs {
+			let x = match instr.src {
+				Reg::Sensor(n) => ground.get(n as usize).copied().unwrap_or(true),
+				Reg::T => t,
+				Reg::J => j,
+			};
+			let y = if instr.dst == Reg::T { &mut t } else { &mut j };
+			*y = match instr.gate {
+				Gate::And => x && *y,
+				Gate::Or => x || *y,
+				Gate::Not => !x,
+			};
+		}
+		j
+	}
+	/// Walks the droid along a hull, starting at its first tile, returning the position of
+	/// the hole it falls into if it doesn't make it across
+	pub fn cross(&self, hull: &[bool]) -> Result<(), usize> {
+		let mut x = 0;
+		while x < hull.len() {
+			x += if self.jumps(&hull[(x + 1).min(hull.len())..]) { 4 } else { 1 };
+			if hull.get(x) == Some(&false) {
+				return Err(x);
+			}
+		}
+		Ok(())
+	}
+}
+impl fmt::Display for Script {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for instr in &self.instrs {
+			writeln!(f, "{}", instr)?;
+		}
+		match self.mode {
+			Mode::Walk => writeln!(f, "WALK"),
+			Mode::Run => writeln!(f, "RUN"),
+		}
+	}
+}
+
+/// Parses a hull drawn with `#` for ground and `.` for holes
+pub fn parse_hull(s: &str) -> Vec<bool> {
+	s.chars().map(|c| c == '#').collect()
+}
+
+/// What the droid's sensors see from a tile, with bit `n` set if `Sensor(n)` sees ground
+type View = u16;
+
+/// A literal in a clause: a sensor, and whether it must see ground (rather than a hole)
+type Lit = (u8, bool);
+
+fn holds(lit: Lit, view: View) -> bool {
+	(view >> lit.0 & 1 == 1) == lit.1
+}
+
+/// Decides where the droid should jump on each hull, by following a single rule: jump as soon
+/// as a hole is coming up, if the droid can still make it across from where it lands, and
+/// otherwise walk. Returns the views the droid jumps from and the views it walks from, or None
+/// if some hull can't be crossed at all.
+fn label(sensors: usize, hulls: &[Vec<bool>]) -> Option<(BTreeSet<View>, BTreeSet<View>)> {
+	let (mut jump, mut walk) = (BTreeSet::new(), BTreeSet::new());
+	for hull in hulls {
+		let ground = |x: usize| hull.get(x).copied().unwrap_or(true);
+		// whether the droid can make it across from each tile
+		let mut ok = vec![true; hull.len() + 4];
+		for x in (0..hull.len()).rev() {
+			ok[x] = hull[x] && (ok[x + 1] || ok[x + 4]);
+		}
+		if !ok[0] {
+			return None;
+		}
+		let mut x = 0;
+		while x < hull.len() {
+			let view = (0..sensors).filter(|&s| ground(x + 1 + s)).fold(0, |v, s| v | 1 << s);
+			if ok[x + 4] && (1..=3).any(|d| !ground(x + d)) {
+				jump.insert(view);
+				x += 4;
+			} else {
+				walk.insert(view);
+				x += 1;
+			}
+		}
+	}
+	Some((jump, walk))
+}
+
+/// Compiles a clause into instructions that AND it into `J`. Only one register is free to
+/// build it in, so the clause needs at most one positive literal, or at most one negative one.
+fn and_clause(clause: &[Lit]) -> Option<Vec<Instr>> {
+	let instr = |gate, src, dst| Instr { gate, src, dst };
+	let sensor = |lit: &Lit| Reg::Sensor(lit.0);
+	let (pos, neg): (Vec<&Lit>, Vec<&Lit>) = clause.iter().partition(|l| l.1);
+	if pos.len() == 1 && neg.is_empty() {
+		return Some(vec![instr(Gate::And, sensor(pos[0]), Reg::J)]);
+	}
+	let mut code = Vec::new();
+	if neg.len() <= 1 {
+		// build up the clause as a disjunction
+		let mut rest = pos.iter();
+		match neg.first() {
+			Some(n) => code.push(instr(Gate::Not, sensor(n), Reg::T)),
+			None => {
+				let p = rest.next().unwrap();
+				code.extend([instr(Gate::Not, sensor(p), Reg::T), instr(Gate::Not, Reg::T, Reg::T)]);
+			},
+		}
+		code.extend(rest.map(|p| instr(Gate::Or, sensor(p), Reg::T)));
+	} else if pos.len() <= 1 {
+		// or as the negation of the conjunction of the literals' opposites
+		let mut rest = neg.iter();
+		match pos.first() {
+			Some(p) => code.push(instr(Gate::Not, sensor(p), Reg::T)),
+			None => {
+				let n = rest.next().unwrap();
+				code.extend([instr(Gate::Not, sensor(n), Reg::T), instr(Gate::Not, Reg::T, Reg::T)]);
+			},
+		}
+		code.extend(rest.map(|n| instr(Gate::And, sensor(n), Reg::T)));
+		code.push(instr(Gate::Not, Reg::T, Reg::T));
+	} else {
+		return None;
+	}
+	code.push(instr(Gate::And, Reg::T, Reg::J));
+	Some(code)
+}
+
+/// Compiles a clause into instructions that set `J` to it, assuming `J` starts out false
+fn set_clause(clause: &[Lit]) -> Vec<Instr> {
+	let mut lits = clause.to_vec();
+	// a negative literal is cheapest first, as it can be written to J directly
+	lits.sort_by_key(|l| l.1);
+	let mut code = Vec::new();
+	for (i, &(s, pos)) in lits.iter().enumerate() {
+		let src = Reg::Sensor(s);
+		match (i, pos) {
+			(_, true) => code.push(Instr { gate: Gate::Or, src, dst: Reg::J }),
+			(0, false) => code.push(Instr { gate: Gate::Not, src, dst: Reg::J }),
+			(_, false) => code.extend([
+				Instr { gate: Gate::Not, src, dst: Reg::T },
+				Instr { gate: Gate::Or, src: Reg::T, dst: Reg::J },
+			]),
+		}
+	}
+	code
+}
+
+/// Finds a script that gets the droid across every hull, if it can find one that fits.
+///
+/// The hulls are first crossed by hand, as decided by [`label`], giving views the droid must
+/// jump from and views it must walk from. The script is then built as a conjunction of
+/// clauses, each an OR of up to [`MAX_CLAUSE`] sensor readings, that holds for every view to
+/// jump from and fails for every view to walk from. Clauses are picked greedily, by how many
+/// more walking views they rule out per instruction they take.
+pub fn synthesize(mode: Mode, hulls: &[Vec<bool>]) -> Option<Script> {
+	let sensors = mode.sensors();
+	let (jump, walk) = label(sensors as usize, hulls)?;
+	if jump.is_empty() {
+		return Some(Script { instrs: Vec::new(), mode });
+	}
+
+	let lits: Vec<Lit> = (0..sensors).flat_map(|s| [(s, true), (s, false)]).collect();
+	let mut candidates: Vec<(Vec<Lit>, Vec<Instr>)> = Vec::new();
+	for size in 1..=MAX_CLAUSE {
+		for clause in lits.iter().copied().combinations(size) {
+			let distinct = clause.iter().map(|l| l.0).collect::<BTreeSet<_>>().len() == size;
+			if !distinct || !jump.iter().all(|&v| clause.iter().any(|&l| holds(l, v))) {
+				continue;
+			}
+			if let Some(code) = and_clause(&clause) {
+				candidates.push((clause, code));
+			}
+		}
+	}
+
+	let mut clauses: Vec<Vec<Lit>> = Vec::new();
+	let mut left = walk;
+	while !left.is_empty() {
+		let ruled_out = |clause: &[Lit]| left.iter().filter(|&&v| !clause.iter().any(|&l| holds(l, v))).count();
+		let (clause, _) = candidates.iter()
+			.filter(|(c, _)| ruled_out(c) > 0)
+			.max_by(|(a, ca), (b, cb)| (ruled_out(a) * cb.len()).cmp(&(ruled_out(b) * ca.len())).then(cb.len().cmp(&ca.len())))?;
+		left.retain(|&v| clause.iter().any(|&l| holds(l, v)));
+		clauses.push(clause.clone());
+	}
+
+	// the clause that saves the most by setting J directly goes first
+	let saving = |c: &Vec<Lit>| and_clause(c).unwrap().len() as isize - set_clause(c).len() as isize;
+	let mut instrs = match clauses.iter().enumerate().max_by_key(|(_, c)| saving(c)) {
+		Some((first, _)) => {
+			let first = clauses.remove(first);
+			set_clause(&first)
+		},
+		// every view left to jump from can be jumped from
+		None => vec![
+			Instr { gate: Gate::Not, src: Reg::Sensor(0), dst: Reg::J },
+			Instr { gate: Gate::Or, src: Reg::Sensor(0), dst: Reg::J },
+		],
+	};
+	for clause in &clauses {
+		instrs.extend(and_clause(clause).unwrap());
+	}
+	let script = Script { instrs, mode };
+	(script.instrs.len() <= MAX_INSTRUCTIONS && hulls.iter().all(|h| script.cross(h).is_ok())).then_some(script)
+}
+
+/// What happened when the springdroid ran a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+	/// The droid made it, and reported the hull damage
+	Damage(ICInt),
+	/// The droid fell into a hole on this hull, starting from its first tile
+	Fell(Vec<bool>),
+}
+
+/// Uploads a script to the springdroid and reports how it did
+pub fn run_script(ic: &Intcode, script: &Script) -> Outcome {
+	let mut con = AsciiConsole::new(ic.clone());
+	con.send(&script.to_string());
+	assert_eq!(con.run(), RunResult::Halted, "springdroid program did not finish");
+	if let Some(&damage) = con.values.last() {
+		return Outcome::Damage(damage);
+	}
+	// the first frame of the replay shows the droid on the hull's first tile
+	let hull = con.lines.iter()
+		.skip_while(|l| !l.starts_with("Didn't make it across"))
+		.find(|l| l.starts_with('#') && l.chars().all(|c| c == '#' || c == '.'))
+		.expect("springdroid failed without showing the hull");
+	Outcome::Fell(parse_hull(hull))
+}
+
+/// Synthesizes scripts until one gets the droid across every hull the Intcode program tries,
+/// learning a new hull from each failure
+pub fn solve(ic: &Intcode, mode: Mode) -> (Script, ICInt) {
+	let mut hulls = Vec::new();
+	loop {
+		let script = synthesize(mode, &hulls).expect("no springscript program fits in memory");
+		match run_script(ic, &script) {
+			Outcome::Damage(damage) => return (script, damage),
+			Outcome::Fell(hull) => {
+				assert!(!hulls.contains(&hull), "script fell on a hull it should cross");
+				hulls.push(hull);
+			},
+		}
+	}
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct Day21;
 
 impl AoCDay for Day21 {
-	type Data<'i> = Vec<u8>;
-	type Answer = usize;
+	type Data<'i> = Intcode;
+	type Answer = ICInt;
 	fn day(&self) -> u8 { 21 }
 	fn parse<'i>(&self, input: &'i str) -> Self::Data<'i> {
-		input.split('\n')
-			.filter_map(aoch::parsing::trimmed)
-			.map(|n| n.parse::<u8>().unwrap())
-			.collect()
+		Intcode::parse(input)
 	}
 	fn part1(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
-		todo!("Day {} Part 1", Self::day(&Self));
+		solve(_data, Mode::Walk).1
 	}
 	fn part2(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
-		todo!("Day {} Part 2", Self::day(&Self));
+		solve(_data, Mode::Run).1
 	}
 }
 
+#[test]
+fn springscript() {
+	let script = Script::parse("NOT A J\nNOT B T\nOR T J\nNOT C T\nOR T J\nAND D J\nWALK\n").unwrap();
+	assert_eq!(script.instrs[1], Instr { gate: Gate::Not, src: Reg::Sensor(1), dst: Reg::T });
+	assert_eq!(Script::parse(&script.to_string()), Ok(script.clone()));
+	assert!(script.jumps(&parse_hull("#.##")));
+	assert!(!script.jumps(&parse_hull("#.#.")));
+	assert_eq!(script.cross(&parse_hull("#####.###########")), Ok(()));
+	assert_eq!(script.cross(&parse_hull("#####.#.##.######")), Err(7));
+
+	let err = |src: &str| Script::parse(src).unwrap_err().to_string();
+	assert_eq!(err("NOT A J\nAND E J\nWALK"), "line 2: sensor E can only be used when running");
+	assert_eq!(err("NOT A B\nRUN"), "line 1: sensor B cannot be written");
+	assert_eq!(err("XOR A J\nRUN"), "line 1: unknown instruction \"XOR\"");
+	assert_eq!(err("NOT A J\n"), "line 1: script must end with WALK or RUN");
+	assert_eq!(err(&("OR A J\n".repeat(16) + "WALK")), "line 16: 16 instructions, but at most 15 fit");
+}
+
+#[test]
+fn synthesis() {
+	let hulls: Vec<_> = ["#####.###########", "#####..#.########", "#####...#########"].iter()
+		.map(|h| parse_hull(h))
+		.collect();
+	let script = synthesize(Mode::Walk, &hulls).unwrap();
+	assert!(script.instrs.len() <= 4, "{}", script);
+	assert!(hulls.iter().all(|h| script.cross(h).is_ok()), "{}", script);
+	assert_eq!(synthesize(Mode::Walk, &[parse_hull("#....#")]), None, "a gap of four can't be jumped");
+}
+
 #[test]
 fn part1() {
 	let cases = [
 		// (TEST_INPUT, 0),
-		(daystr!("21"), 0),
+		(daystr!("21"), 19357544),
 	];
 	test_runner::<_, _>(Day21, DayPart::Part1, &cases);
 }
@@ -35,7 +481,7 @@ fn part1() {
 fn part2() {
 	let cases = [
 		// (TEST_INPUT, 0),
-		(daystr!("21"), 0),
+		(daystr!("21"), 1144498646),
 	];
 	test_runner::<_, _>(Day21, DayPart::Part2, &cases);
 }
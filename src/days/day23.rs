@@ -1,33 +1,165 @@
+use std::collections::VecDeque;
 
 #[allow(unused_imports)]
 use aoch::{AoCDay, DayPart, daystr, run_test, test_runner};
 
+use crate::intcode::{Intcode, ICInt};
+use crate::intcode::scheduler::{Outcome, Route, Scheduler};
+
+/// Address of the NAT, which watches over the network
+pub const NAT: ICInt = 255;
+
+/// A packet seen on the network. Packets sent by the NAT come from address 255.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+	pub src: ICInt,
+	pub dest: ICInt,
+	pub x: ICInt,
+	pub y: ICInt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatEvent {
+	/// The NAT received a packet, replacing any it was holding
+	Received { x: ICInt, y: ICInt },
+	/// The network was idle, so the NAT sent its packet to address 0
+	Woke { x: ICInt, y: ICInt },
+}
+
+/// A network of computers all running the same program, exchanging `(dest, x, y)` packets
+#[derive(Debug, Clone)]
+pub struct Network {
+	sched: Scheduler,
+	nat: Option<(ICInt, ICInt)>,
+	events: VecDeque<NatEvent>,
+	/// Every packet sent so far, including those sent by the NAT
+	pub log: Vec<Packet>,
+}
+impl Network {
+	pub fn new(prog: &Intcode, size: usize) -> Network {
+		let nodes = (0..size)
+			.map(|addr| {
+				let mut ic = prog.clone();
+				ic.input.push_back(addr as ICInt);
+				ic
+			})
+			.collect();
+		let mut sched = Scheduler::new(nodes);
+		for addr in 0..size {
+			sched.route(addr, Route::Packets(3));
+		}
+		sched.idle_input(-1);
+		sched.log_packets();
+
+		Network {
+			sched,
+			nat: None,
+			events: VecDeque::new(),
+			log: Vec::new(),
+		}
+	}
+	/// The packet the NAT will send when the network next goes idle
+	pub fn nat(&self) -> Option<(ICInt, ICInt)> {
+		self.nat
+	}
+
+	/// Runs the network until something happens at the NAT.
+	/// Returns why the network stopped if it can no longer make progress.
+	pub fn next_event(&mut self) -> Result<NatEvent, Outcome> {
+		loop {
+			if let Some(ev) = self.events.pop_front() {
+				return Ok(ev);
+			}
+
+			let outcome = self.sched.run();
+			self.log.extend(self.sched.packet_log.drain(..).map(|p| Packet {
+				src: p.src as ICInt,
+				dest: p.dest,
+				x: p.data[0],
+				y: p.data[1],
+			}));
+			match outcome {
+				Outcome::Outbound => {
+					while let Some(p) = self.sched.outbound.pop_front() {
+						assert_eq!(p.dest, NAT, "packet sent to unknown address by {}", p.src);
+						let (x, y) = (p.data[0], p.data[1]);
+						self.nat = Some((x, y));
+						self.events.push_back(NatEvent::Received { x, y });
+					}
+				},
+				Outcome::Deadlock => {
+					let (x, y) = match self.nat {
+						Some(p) => p,
+						None => return Err(Outcome::Deadlock),
+					};
+					self.sched.machines[0].input.extend([x, y]);
+					self.log.push(Packet { src: NAT, dest: 0, x, y });
+					self.events.push_back(NatEvent::Woke { x, y });
+				},
+				bad => return Err(bad),
+			}
+		}
+	}
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Day23;
 
 impl AoCDay for Day23 {
-	type Data<'i> = Vec<u8>;
-	type Answer = usize;
+	type Data<'i> = Intcode;
+	type Answer = ICInt;
 	fn day(&self) -> u8 { 23 }
 	fn parse<'i>(&self, input: &'i str) -> Self::Data<'i> {
-		input.split('\n')
-			.filter_map(aoch::parsing::trimmed)
-			.map(|n| n.parse::<u8>().unwrap())
-			.collect()
+		Intcode::parse(input)
 	}
 	fn part1(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
-		todo!("Day {} Part 1", Self::day(&Self));
+		let mut net = Network::new(_data, 50);
+		loop {
+			match net.next_event() {
+				Ok(NatEvent::Received { y, .. }) => return y,
+				Ok(_) => {},
+				Err(outcome) => panic!("network stopped before reaching the NAT: {:?}", outcome),
+			}
+		}
 	}
 	fn part2(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
-		todo!("Day {} Part 2", Self::day(&Self));
+		let mut net = Network::new(_data, 50);
+		let mut last_y = None;
+		loop {
+			match net.next_event() {
+				Ok(NatEvent::Woke { y, .. }) => {
+					if last_y == Some(y) {
+						return y;
+					}
+					last_y = Some(y);
+				},
+				Ok(_) => {},
+				Err(outcome) => panic!("network stopped: {:?}", outcome),
+			}
+		}
 	}
 }
 
+#[test]
+fn packet_log() {
+	let mut net = Network::new(&Intcode::parse(daystr!("23")), 50);
+	let first = net.next_event().unwrap();
+	let last = *net.log.last().unwrap();
+	assert_eq!(last.dest, NAT);
+	assert_eq!(first, NatEvent::Received { x: last.x, y: last.y });
+	assert!(net.log.iter().all(|p| (0..50).contains(&p.src) && (p.dest < 50 || p.dest == NAT)));
+
+	while !matches!(net.next_event().unwrap(), NatEvent::Woke { .. }) {}
+	let woke = *net.log.last().unwrap();
+	assert_eq!((woke.src, woke.dest), (NAT, 0));
+	assert_eq!(Some((woke.x, woke.y)), net.nat());
+}
+
 #[test]
 fn part1() {
 	let cases = [
 		// (TEST_INPUT, 0),
-		(daystr!("23"), 0),
+		(daystr!("23"), 26744),
 	];
 	test_runner::<_, _>(Day23, DayPart::Part1, &cases);
 }
@@ -35,7 +167,7 @@ fn part1() {
 fn part2() {
 	let cases = [
 		// (TEST_INPUT, 0),
-		(daystr!("23"), 0),
+		(daystr!("23"), 19498),
 	];
 	test_runner::<_, _>(Day23, DayPart::Part2, &cases);
 }
@@ -1,33 +1,332 @@
 
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
 #[allow(unused_imports)]
 use aoch::{AoCDay, DayPart, daystr, run_test, test_runner};
 
+use crate::intcode::{Intcode, RunConfig, RunResult, ICInt};
+use crate::intcode::ascii::AsciiConsole;
+
+/// Instructions allowed per command, so that an item that traps the droid in a loop is noticed
+const STEP_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Dir {
+	North,
+	South,
+	East,
+	West,
+}
+impl Dir {
+	fn parse(s: &str) -> Option<Dir> {
+		match s {
+			"north" => Some(Dir::North),
+			"south" => Some(Dir::South),
+			"east" => Some(Dir::East),
+			"west" => Some(Dir::West),
+			_ => None,
+		}
+	}
+	fn name(&self) -> &'static str {
+		match self {
+			Dir::North => "north",
+			Dir::South => "south",
+			Dir::East => "east",
+			Dir::West => "west",
+		}
+	}
+	fn opposite(&self) -> Dir {
+		match self {
+			Dir::North => Dir::South,
+			Dir::South => Dir::North,
+			Dir::East => Dir::West,
+			Dir::West => Dir::East,
+		}
+	}
+}
+
+/// A room as described by the game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Room {
+	pub name: String,
+	pub description: String,
+	/// Each door, with the name of the room it leads to once that's known
+	pub doors: BTreeMap<Dir, Option<String>>,
+	pub items: Vec<String>,
+}
+
+/// Parses every room description in the game's output, in the order they were printed.
+/// Being turned away by the pressure-sensitive floor prints two: the floor, then the room
+/// the droid is sent back to.
+fn parse_rooms(text: &str) -> Vec<Room> {
+	let mut rooms: Vec<Room> = Vec::new();
+	let mut section = "";
+	for line in text.lines().map(str::trim) {
+		if let Some(name) = line.strip_prefix("== ").and_then(|l| l.strip_suffix(" ==")) {
+			rooms.push(Room { name: name.to_owned(), description: String::new(), doors: BTreeMap::new(), items: Vec::new() });
+			section = "description";
+			continue;
+		}
+		let room = match rooms.last_mut() {
+			Some(room) => room,
+			None => continue,
+		};
+		match (line, line.strip_prefix("- ")) {
+			("", _) => section = "",
+			("Doors here lead:", _) => section = "doors",
+			("Items here:", _) => section = "items",
+			(_, Some(door)) if section == "doors" => {
+				room.doors.insert(Dir::parse(door).unwrap_or_else(|| panic!("unknown door: {:?}", door)), None);
+			},
+			(_, Some(item)) if section == "items" => room.items.push(item.to_owned()),
+			(line, _) if section == "description" => {
+				if !room.description.is_empty() {
+					room.description.push('\n');
+				}
+				room.description.push_str(line);
+			},
+			_ => {},
+		}
+	}
+	rooms
+}
+
+/// Finds the airlock password given once the droid is let past the pressure-sensitive floor
+fn parse_password(text: &str) -> Option<ICInt> {
+	let (_, rest) = text.split_once("by typing ")?;
+	rest.split_whitespace().next()?.parse().ok()
+}
+
+/// Plays the text adventure, mapping the ship and picking up everything that can safely be
+/// carried, then working out which items weigh enough to get past the security checkpoint
+#[derive(Debug)]
+pub struct Explorer {
+	con: AsciiConsole,
+	/// Every room visited, by name. The pressure-sensitive floor is never entered for long
+	/// enough to be included.
+	pub rooms: BTreeMap<String, Room>,
+	/// Name of the room the droid is in
+	pub room: String,
+	pub inventory: BTreeSet<String>,
+	/// Items that end the game or leave the droid stuck once picked up
+	pub dangerous: BTreeSet<String>,
+	/// The room leading to the pressure-sensitive floor, and the door to it
+	pub checkpoint: Option<(String, Dir)>,
+}
+impl Explorer {
+	pub fn new(ic: Intcode) -> Explorer {
+		let mut con = AsciiConsole::new(ic);
+		assert_eq!(con.run(), RunResult::Starved, "game ended before its first command");
+		let room = parse_rooms(&con.take_text()).pop().expect("game did not describe the starting room");
+		Explorer {
+			con,
+			room: room.name.clone(),
+			rooms: BTreeMap::from([(room.name.clone(), room)]),
+			inventory: BTreeSet::new(),
+			dangerous: BTreeSet::new(),
+			checkpoint: None,
+		}
+	}
+
+	/// Sends a command, returning how the game stopped and what it printed
+	fn send(&mut self, cmd: &str) -> (RunResult, String) {
+		self.con.send_line(cmd);
+		let rr = self.con.run_with(&RunConfig { max_steps: Some(STEP_LIMIT), ..Default::default() });
+		self.con.take_values();
+		(rr, self.con.take_text())
+	}
+
+	/// Tries to go through a door, returning false if the droid couldn't move
+	fn go(&mut self, dir: Dir) -> bool {
+		let (rr, text) = self.send(dir.name());
+		let mut seen = parse_rooms(&text);
+		if rr != RunResult::Starved || seen.is_empty() {
+			return false;
+		}
+		let from = std::mem::take(&mut self.room);
+		let dest = seen[0].name.clone();
+		// the floor sends the droid back where it came from
+		let now = seen.pop().unwrap();
+		if now.name != dest {
+			self.checkpoint = Some((from.clone(), dir));
+		}
+		self.rooms.get_mut(&from).unwrap().doors.insert(dir, Some(dest.clone()));
+
+		self.room = now.name.clone();
+		match self.rooms.get_mut(&now.name) {
+			Some(room) => room.items = now.items,
+			None => { self.rooms.insert(now.name.clone(), now); },
+		}
+		if let Some(door) = self.rooms.get_mut(&dest).and_then(|r| r.doors.get_mut(&dir.opposite())) {
+			*door = Some(from);
+		}
+		true
+	}
+
+	/// Finds the shortest route from the current room to the nearest room that `goal` accepts
+	fn route(&self, goal: impl Fn(&Room) -> bool) -> Option<(String, Vec<Dir>)> {
+		let mut prev: BTreeMap<&str, Option<(&str, Dir)>> = BTreeMap::from([(self.room.as_str(), None)]);
+		let mut queue = VecDeque::from([self.room.as_str()]);
+		while let Some(name) = queue.pop_front() {
+			let room = &self.rooms[name];
+			if goal(room) {
+				let mut path = Vec::new();
+				let mut at = name;
+				while let Some((p, dir)) = prev[at] {
+					path.push(dir);
+					at = p;
+				}
+				path.reverse();
+				return Some((name.to_owned(), path));
+			}
+			for (&dir, dest) in &room.doors {
+				let dest = match dest {
+					Some(dest) if self.rooms.contains_key(dest) && !prev.contains_key(dest.as_str()) => dest.as_str(),
+					_ => continue,
+				};
+				if self.checkpoint.as_ref().map_or(false, |(c, d)| c == name && *d == dir) {
+					continue;
+				}
+				prev.insert(dest, Some((name, dir)));
+				queue.push_back(dest);
+			}
+		}
+		None
+	}
+	fn follow(&mut self, path: &[Dir]) {
+		for &dir in path {
+			assert!(self.go(dir), "unable to retrace a step {} from {}", dir.name(), self.room);
+		}
+	}
+
+	/// Picks up an item if doing so is safe, returning whether it was
+	fn take(&mut self, item: &str) -> bool {
+		let before = self.con.ic.clone();
+		let (rr, _) = self.send(&format!("take {}", item));
+		// some items can only be found out by trying to leave with them
+		let moved = rr == RunResult::Starved && {
+			let after = self.con.ic.clone();
+			let exit = self.rooms[&self.room].doors.keys().copied()
+				.find(|&dir| self.checkpoint.as_ref().map_or(true, |(c, d)| *c != self.room || *d != dir))
+				.expect("room has no doors");
+			let (rr, text) = self.send(exit.name());
+			self.con.ic = after;
+			rr == RunResult::Starved && !parse_rooms(&text).is_empty()
+		};
+		if moved {
+			self.inventory.insert(item.to_owned());
+			self.rooms.get_mut(&self.room).unwrap().items.retain(|i| i != item);
+		} else {
+			self.con.ic = before;
+			self.dangerous.insert(item.to_owned());
+		}
+		moved
+	}
+
+	/// Visits every room reachable without crossing the pressure-sensitive floor, picking up
+	/// whatever can be carried along the way
+	pub fn explore(&mut self) {
+		loop {
+			let items: Vec<_> = self.rooms[&self.room].items.iter()
+				.filter(|i| !self.dangerous.contains(*i))
+				.cloned()
+				.collect();
+			for item in items {
+				self.take(&item);
+			}
+
+			let (_, path) = match self.route(|r| r.doors.values().any(Option::is_none)) {
+				Some(found) => found,
+				None => return,
+			};
+			self.follow(&path);
+			let dir = self.rooms[&self.room].doors.iter()
+				.find(|(_, dest)| dest.is_none())
+				.map(|(&dir, _)| dir)
+				.unwrap();
+			assert!(self.go(dir), "unable to go {} from {}", dir.name(), self.room);
+		}
+	}
+
+	/// Tries every combination of the items carried on the pressure-sensitive floor until one
+	/// weighs the right amount, returning the airlock password. Consecutive combinations differ
+	/// by a single item, so that each attempt takes one `take` or `drop`.
+	pub fn pass_checkpoint(&mut self) -> Option<ICInt> {
+		let (checkpoint, floor) = self.checkpoint.clone()?;
+		let (_, path) = self.route(|r| r.name == checkpoint)?;
+		self.follow(&path);
+
+		let items: Vec<String> = self.inventory.iter().cloned().collect();
+		let mut held = vec![true; items.len()];
+		for i in 0..1usize << items.len() {
+			if i != 0 {
+				let flip = i.trailing_zeros() as usize;
+				let cmd = if held[flip] { "drop" } else { "take" };
+				held[flip] = !held[flip];
+				let (rr, _) = self.send(&format!("{} {}", cmd, items[flip]));
+				assert_eq!(rr, RunResult::Starved, "unable to {} {}", cmd, items[flip]);
+			}
+			let (_, text) = self.send(floor.name());
+			if let Some(password) = parse_password(&text) {
+				self.inventory = items.iter().zip(&held).filter(|(_, &h)| h).map(|(i, _)| i.clone()).collect();
+				return Some(password);
+			}
+		}
+		None
+	}
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Day25;
 
 impl AoCDay for Day25 {
-	type Data<'i> = Vec<u8>;
-	type Answer = usize;
+	type Data<'i> = Intcode;
+	type Answer = ICInt;
 	fn day(&self) -> u8 { 25 }
 	fn parse<'i>(&self, input: &'i str) -> Self::Data<'i> {
-		input.split('\n')
-			.filter_map(aoch::parsing::trimmed)
-			.map(|n| n.parse::<u8>().unwrap())
-			.collect()
+		Intcode::parse(input)
 	}
 	fn part1(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
-		todo!("Day {} Part 1", Self::day(&Self));
+		let mut explorer = Explorer::new(_data.clone());
+		explorer.explore();
+		explorer.pass_checkpoint().expect("no combination of items got past the checkpoint")
 	}
 	fn part2(&self, _data: &mut Self::Data<'_>) -> Self::Answer {
 		todo!("Day {} Part 2", Self::day(&Self));
 	}
 }
 
+#[test]
+fn room_descriptions() {
+	let text = "\n\n\n== Pressure-Sensitive Floor ==\nAnalyzing...\n\nDoors here lead:\n- south\n\nA loud, robotic voice says \"Alert! Droids on this ship are heavier than the detected value!\" and you are ejected back to the checkpoint.\n\n\n\n== Security Checkpoint ==\nIn the next room, a pressure-sensitive floor will verify your identity.\n\nDoors here lead:\n- north\n- west\n\nItems here:\n- fuel cell\n\nCommand?\n";
+	let rooms = parse_rooms(text);
+	assert_eq!(rooms.len(), 2);
+	assert_eq!(rooms[0].name, "Pressure-Sensitive Floor");
+	assert_eq!(rooms[1], Room {
+		name: "Security Checkpoint".to_owned(),
+		description: "In the next room, a pressure-sensitive floor will verify your identity.".to_owned(),
+		doors: BTreeMap::from([(Dir::North, None), (Dir::West, None)]),
+		items: vec!["fuel cell".to_owned()],
+	});
+	assert_eq!(parse_password("You should be able to get in by typing 123 on the keypad at the main airlock."), Some(123));
+}
+
+#[test]
+fn ship_map() {
+	let mut explorer = Explorer::new(Intcode::parse(daystr!("25")));
+	explorer.explore();
+	assert!(explorer.rooms.values().all(|r| r.doors.values().all(Option::is_some)));
+	assert!(explorer.dangerous.contains("infinite loop"));
+	assert!(explorer.dangerous.contains("giant electromagnet"));
+	let (checkpoint, _) = explorer.checkpoint.clone().unwrap();
+	assert_eq!(checkpoint, "Security Checkpoint");
+}
+
 #[test]
 fn part1() {
 	let cases = [
 		// (TEST_INPUT, 0),
-		(daystr!("25"), 0),
+		(daystr!("25"), 201327120),
 	];
 	test_runner::<_, _>(Day25, DayPart::Part1, &cases);
 }
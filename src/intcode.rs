@@ -1,11 +1,34 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::collections::hash_map::DefaultHasher;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 
-// TODO: detect infinite loops?
-// take hash of (self.pc, self.ram) after every write. if same, then loop has been detected.
+pub mod ascii;
+pub mod asm;
+pub mod cfg;
+pub mod debugger;
+pub mod decompile;
+pub mod disasm;
+pub mod io;
+pub mod memory;
+pub mod profile;
+pub mod scheduler;
+pub mod snapshot;
+pub mod terminal;
+pub mod trace;
+pub mod undo;
+pub mod watch;
+pub mod word;
 
+use io::IntcodeIo;
+use memory::{Memory, Ram};
+use profile::Profile;
+use trace::{IoEvent, Trace, TraceStep};
+use undo::{UndoLog, UndoStep};
+use watch::{Access, MemAccess, Watch};
+use word::Word;
+
+/// The default word type, used wherever a program's values aren't otherwise specified
 pub type ICInt = i128;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -21,14 +44,13 @@ impl Instruction {
     fn instr(&self) -> u8 {
         (self.0 % 100) as u8
     }
-    fn param(&self, i: usize) -> ParamMode {
+    /// Decodes the mode of parameter `i`, returning the offending digit if it is not a known mode.
+    fn param(&self, i: usize) -> Result<ParamMode, u8> {
         match self.0 / (10 as ICInt).pow(2+i as u32) % 10 {
-            0 => ParamMode::Position,
-            1 => ParamMode::Immediate,
-            2 => ParamMode::Relative,
-            u => {
-                unreachable!("unknown parameter mode on instruction {} for parameter {}: {}", self.0, i, u);
-            },
+            0 => Ok(ParamMode::Position),
+            1 => Ok(ParamMode::Immediate),
+            2 => Ok(ParamMode::Relative),
+            u => Err(u.unsigned_abs() as u8),
         }
     }
 }
@@ -41,13 +63,127 @@ impl fmt::Debug for Instruction {
     }
 }
 
+/// An instruction word with its opcode and parameter modes already split out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Decoded {
+    op: u8,
+    modes: [u8; 3],
+}
+impl Decoded {
+    fn new<W: Word>(word: &W) -> Decoded {
+        let word = word.to_icint();
+        let digit = |div: ICInt| (word / div % 10).unsigned_abs() as u8;
+        Decoded {
+            op: Instruction(word).instr(),
+            modes: [digit(100), digit(1000), digit(10000)],
+        }
+    }
+    #[inline]
+    fn instr(&self) -> u8 {
+        self.op
+    }
+    #[inline]
+    fn param(&self, i: usize) -> Result<ParamMode, u8> {
+        match self.modes[i] {
+            0 => Ok(ParamMode::Position),
+            1 => Ok(ParamMode::Immediate),
+            2 => Ok(ParamMode::Relative),
+            u => Err(u),
+        }
+    }
+}
+
+/// Decoded instructions by address, along with the word they were decoded from. Entries are
+/// checked against the word in memory before they are used, so self-modifying code and writes
+/// to `ram` from outside both invalidate them.
+#[derive(Clone)]
+struct DecodeCache<W>(Vec<Option<(W, Decoded)>>);
+impl<W> Default for DecodeCache<W> {
+    fn default() -> DecodeCache<W> {
+        DecodeCache(Vec::new())
+    }
+}
+/// The cache never changes how a machine behaves, so it is ignored when comparing machines
+impl<W> PartialEq for DecodeCache<W> {
+    fn eq(&self, _: &DecodeCache<W>) -> bool {
+        true
+    }
+}
+impl<W> Eq for DecodeCache<W> {}
+impl<W> fmt::Debug for DecodeCache<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DecodeCache({} entries)", self.0.iter().flatten().count())
+    }
+}
+
 #[test]
 fn instr_decode() {
     let instr = Instruction(21002);
     assert_eq!(instr.instr(), 2);
-    assert_eq!(instr.param(0), ParamMode::Position);
-    assert_eq!(instr.param(1), ParamMode::Immediate);
-    assert_eq!(instr.param(2), ParamMode::Relative);
+    assert_eq!(instr.param(0), Ok(ParamMode::Position));
+    assert_eq!(instr.param(1), Ok(ParamMode::Immediate));
+    assert_eq!(instr.param(2), Ok(ParamMode::Relative));
+    assert_eq!(Instruction(301).param(0), Err(3));
+
+    for word in [21002, 301, 99, 1105, 22201, 904] {
+        let d = Decoded::new(&word);
+        let instr = Instruction(word);
+        assert_eq!(d.instr(), instr.instr());
+        assert!((0..3).all(|i| d.param(i) == instr.param(i)), "{}", word);
+    }
+}
+
+#[test]
+fn self_modifying() {
+    // replaces its first instruction with a halt after it has been cached
+    let mut ic = Intcode::assemble("
+        loop: out [n]
+              add [n], #1, [n]
+              add #99, #0, [loop]
+              jnz #1, #loop
+        n:    db 7
+    ").unwrap();
+    assert_eq!(ic.run(), RunResult::Halted);
+    assert_eq!(ic.output, vec![7]);
+
+    ic.reset();
+    ic.ram[0] = 104;
+    assert_eq!(ic.run(), RunResult::Halted);
+    assert_eq!(ic.output, vec![13], "outside writes are picked up too");
+}
+
+/// An error raised by the program being executed, rather than by the interpreter itself.
+/// Values from the program are converted to the default word type, saturating if needed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// A jump instruction attempted to set the program counter to a negative value
+    NegativeJump { pc: usize, target: ICInt },
+    /// An instruction attempted to write to an immediate-mode parameter
+    ImmediateWrite { pc: usize },
+    /// An instruction used a parameter mode other than position, immediate or relative
+    BadParamMode { pc: usize, digit: u8 },
+    /// A parameter resolved to a negative memory address
+    NegativeAddress { pc: usize, addr: ICInt },
+    /// The instruction at `pc` was executed more times than allowed by [`RunConfig`], likely
+    /// an infinite loop
+    StepLimit { pc: usize },
+    /// A write to `addr` needed more memory than the machine is allowed
+    MemoryLimit { pc: usize, addr: usize },
+    /// An arithmetic result didn't fit in a checked word, or an address didn't fit in memory
+    Overflow { pc: usize },
+}
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::NegativeJump { pc, target } => write!(f, "attempt to set program counter to negative value ({}) @ PC={}", target, pc),
+            Fault::ImmediateWrite { pc } => write!(f, "attempt to use immediate value as output parameter @ PC={}", pc),
+            Fault::BadParamMode { pc, digit } => write!(f, "unknown parameter mode {} @ PC={}", digit, pc),
+            Fault::NegativeAddress { pc, addr } => write!(f, "attempt to access negative address ({}) @ PC={}", addr, pc),
+            Fault::StepLimit { pc } => write!(f, "instruction at {} exceeded its execution limit", pc),
+            Fault::MemoryLimit { pc, addr } => write!(f, "memory limit exceeded writing to {} @ PC={}", addr, pc),
+            Fault::Overflow { pc } => write!(f, "value out of range @ PC={}", pc),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -56,45 +192,172 @@ pub enum RunResult {
     Halted,
     Starved,
     InvalidInstruction(Instruction),
+    /// The machine returned to a state it had already been in, so will never stop
     RepeatedState,
+    /// The run used up its step budget. The machine can be run again to continue.
+    OutOfSteps,
+    Fault(Fault),
+}
+
+/// How to detect that a machine is looping forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CycleCheck {
+    /// Hashes the machine's state every this many steps, stopping when a hash repeats
+    Periodic(usize),
+    /// Brent's algorithm, which hashes every step but only remembers one state
+    Brent,
 }
 
+/// Limits on a single call to [`Intcode::run_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunConfig {
+    /// Stops with [`RunResult::OutOfSteps`] after this many instructions
+    pub max_steps: Option<usize>,
+    /// Faults once any one instruction is executed this many times. Counting hits costs a
+    /// hash map update per step, so by default this is only enabled in debug builds.
+    pub max_hits_per_pc: Option<usize>,
+    /// Stops with [`RunResult::RepeatedState`] if the machine is caught in a loop.
+    /// States are only compared between inputs read from an [`IntcodeIo`], since the
+    /// values it supplies aren't part of the machine's state.
+    pub cycle_check: Option<CycleCheck>,
+    /// Reuses decoded instructions, rather than decoding each instruction every time it runs
+    pub decode_cache: bool,
+}
+impl Default for RunConfig {
+    fn default() -> RunConfig {
+        RunConfig {
+            max_steps: None,
+            max_hits_per_pc: cfg!(debug_assertions).then(|| 10_000_000),
+            cycle_check: None,
+            decode_cache: true,
+        }
+    }
+}
+
+/// Tracks hashes of the machine's state during a run
+enum CycleDetector {
+    Periodic { every: usize, since: usize, seen: HashSet<u64> },
+    Brent { power: usize, lam: usize, tortoise: Option<u64> },
+}
+impl CycleDetector {
+    fn new(check: CycleCheck) -> CycleDetector {
+        match check {
+            CycleCheck::Periodic(every) => {
+                assert!(every > 0, "cycle check period must be at least 1");
+                CycleDetector::Periodic { every, since: 0, seen: HashSet::new() }
+            },
+            CycleCheck::Brent => CycleDetector::Brent { power: 1, lam: 0, tortoise: None },
+        }
+    }
+    fn clear(&mut self) {
+        match self {
+            CycleDetector::Periodic { since, seen, .. } => {
+                *since = 0;
+                seen.clear();
+            },
+            CycleDetector::Brent { power, lam, tortoise } => {
+                *power = 1;
+                *lam = 0;
+                *tortoise = None;
+            },
+        }
+    }
+    /// Called after every step. Returns true once the machine is known to be in a loop.
+    fn repeated<F: FnOnce() -> u64>(&mut self, hash: F) -> bool {
+        match self {
+            CycleDetector::Periodic { every, since, seen } => {
+                *since += 1;
+                if since < every {
+                    return false;
+                }
+                *since = 0;
+                !seen.insert(hash())
+            },
+            CycleDetector::Brent { power, lam, tortoise } => {
+                let hare = hash();
+                if *tortoise == Some(hare) {
+                    return true;
+                }
+                // move the tortoise up to the hare at every power of two
+                *lam += 1;
+                if lam == power {
+                    *tortoise = Some(hare);
+                    *power *= 2;
+                    *lam = 0;
+                }
+                false
+            },
+        }
+    }
+}
+
+/// An Intcode machine, computing with words of type `W`
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
-pub struct Intcode {
+pub struct Intcode<W: Word = ICInt> {
 	pc: usize,
-    relative_base: ICInt,
+    relative_base: W,
 	stepped: usize,
-	pub ram: Vec<ICInt>,
-	original: Vec<ICInt>,
-    pub input: Vec<ICInt>,
-    pub output: Vec<ICInt>,
+	pub ram: Ram<W>,
+	original: Vec<W>,
+    pub input: VecDeque<W>,
+    pub output: VecDeque<W>,
     prev_states: HashSet<u64>,
+    watchpoints: BTreeMap<usize, Watch>,
+    /// Every access to a watched address, in execution order
+    pub watch_log: Vec<MemAccess<W>>,
+    decoded: DecodeCache<W>,
+    trace: Option<Trace<W>>,
+    undo: Option<UndoLog<W>>,
+    profile: Option<Profile>,
+    /// Limits used by [`Intcode::run`] and [`Intcode::run_io`], which are kept across resets
+    pub run_config: RunConfig,
 }
 impl Intcode {
 	pub fn new(ram: Vec<ICInt>) -> Intcode {
+		Intcode::from_words(ram)
+	}
+    pub fn parse(s: &str) -> Intcode {
+		Intcode::parse_words(s)
+    }
+}
+impl<W: Word> Intcode<W> {
+    /// Creates a machine with a word type other than the default
+	pub fn from_words(ram: Vec<W>) -> Intcode<W> {
 		Intcode {
 			original: ram.clone(),
-			ram,
+			ram: Ram::from(ram),
             ..Default::default()
 		}
 	}
-    pub fn parse(s: &str) -> Intcode {
-        let v: Vec<ICInt> = s.split(',')
+    /// Parses a program for a machine with a word type other than the default
+    pub fn parse_words(s: &str) -> Intcode<W> {
+        let v: Vec<W> = s.split(',')
 			.filter_map(aoch::parsing::trimmed)
-            .map(|n| n.parse::<_>().unwrap())
+            .map(|n| n.parse::<_>().unwrap_or_else(|_| panic!("invalid Intcode value: {:?}", n)))
             .collect();
-		Intcode::new(v)
+		Intcode::from_words(v)
     }
 	pub fn reset(&mut self) {
 		self.pc = 0;
+        self.relative_base = W::default();
 		self.stepped = 0;
-        self.ram.resize(self.original.len(), 0); // shrink if necessary
-        self.ram.copy_from_slice(&self.original); // copy original back into it
-        self.input = Vec::new();
-        self.output = Vec::new();
+        self.ram.load(&self.original);
+        self.input.clear();
+        self.output.clear();
+        self.prev_states.clear();
+        self.watch_log.clear();
+        if let Some(trace) = &mut self.trace {
+            trace.steps.clear();
+        }
+        if let Some(undo) = &mut self.undo {
+            undo.steps.clear();
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.restart();
+        }
 	}
     pub fn is_halted(&self) -> bool {
-        Instruction(self.ram[self.pc as usize]).instr() == 99
+        Decoded::new(&self.ram[self.pc]).instr() == 99
     }
     fn hash_value(&self) -> u64 {
         let mut s = DefaultHasher::new();
@@ -112,121 +375,245 @@ impl Intcode {
         self.stepped
     }
 
-    fn resolve_param(&self, instr: Instruction, ind: usize) -> ICInt {
-        //param.resolve(&self.ram, self.pc + offset, self.relative_base)
-        let param = instr.param(ind);
+    fn param_mode(&self, instr: Decoded, ind: usize) -> Result<ParamMode, Fault> {
+        instr.param(ind).map_err(|digit| Fault::BadParamMode { pc: self.pc, digit })
+    }
+    /// Converts a word from the program into an address, or the fault caused by using it as one
+    fn to_addr(&self, addr: &W) -> Result<usize, Fault> {
+        match addr.to_usize() {
+            Some(addr) => Ok(addr),
+            None if addr.is_negative() => Err(Fault::NegativeAddress { pc: self.pc, addr: addr.to_icint() }),
+            None => Err(Fault::Overflow { pc: self.pc }),
+        }
+    }
+    /// Resolves the memory address that parameter `ind` of `instr` refers to.
+    fn param_addr(&self, instr: Decoded, ind: usize) -> Result<usize, Fault> {
         let arg_ptr = self.pc + ind + 1;
-        let out_ptr = match param {
-            ParamMode::Position => self.ram[arg_ptr] as usize,
-            ParamMode::Immediate => arg_ptr,
-            ParamMode::Relative if self.relative_base == 0 => self.ram[arg_ptr] as usize,
-            ParamMode::Relative => (self.relative_base + self.ram[arg_ptr]) as usize,
-        };
-        if out_ptr >= self.ram.len() {
-            return 0;
+        match self.param_mode(instr, ind)? {
+            ParamMode::Position => self.to_addr(&self.ram[arg_ptr]),
+            ParamMode::Immediate => Ok(arg_ptr),
+            ParamMode::Relative => {
+                let addr = self.relative_base.try_add(&self.ram[arg_ptr]).ok_or(Fault::Overflow { pc: self.pc })?;
+                self.to_addr(&addr)
+            },
+        }
+    }
+    fn resolve_param(&mut self, instr: Decoded, ind: usize) -> Result<W, Fault> {
+        let out_ptr = self.param_addr(instr, ind)?;
+        let value = self.ram[out_ptr].clone();
+        if !self.watchpoints.is_empty() {
+            self.watch_param(ind, out_ptr, Access::Read, &value);
+        }
+        if let Some(step) = self.trace_step() {
+            step.operands.push(value.clone());
+        }
+        Ok(value)
+    }
+    /// Resolves the address that output parameter `ind` of `instr` writes to
+    fn out_addr(&self, instr: Decoded, ind: usize) -> Result<usize, Fault> {
+        if self.param_mode(instr, ind)? == ParamMode::Immediate {
+            return Err(Fault::ImmediateWrite { pc: self.pc });
         }
-        self.ram[out_ptr]
+        self.param_addr(instr, ind)
+    }
+    fn write_param(&mut self, instr: Decoded, ind: usize, value: W) -> Result<(), Fault> {
+        let out_ptr = self.out_addr(instr, ind)?;
+        self.write_addr(ind, out_ptr, value)
     }
-    fn resolve_param_mut(&mut self, instr: Instruction, ind: usize) -> &mut ICInt {
-        let param = instr.param(ind);
+    /// Writes the value of output parameter `ind`, once its address is known
+    fn write_addr(&mut self, ind: usize, out_ptr: usize, value: W) -> Result<(), Fault> {
+        if !self.watchpoints.is_empty() {
+            self.watch_param(ind, out_ptr, Access::Write, &value);
+        }
+        if let Some(step) = self.trace_step() {
+            step.writes.push((out_ptr, value.clone()));
+        }
+        if self.undo.is_some() {
+            let old = self.ram[out_ptr].clone();
+            if let Some(step) = self.undo_step() {
+                step.writes.push((out_ptr, old));
+            }
+        }
+        self.ram.set(out_ptr, value).map_err(|_| Fault::MemoryLimit { pc: self.pc, addr: out_ptr })
+    }
+    /// Logs the operand fetch and data access of a parameter, if either address is watched.
+    fn watch_param(&mut self, ind: usize, addr: usize, kind: Access, value: &W) {
         let arg_ptr = self.pc + ind + 1;
-        let arg_val = self.ram[arg_ptr];
-        let out_ptr = match param {
-            ParamMode::Position => arg_val as usize,
-            ParamMode::Immediate => panic!("attempt to use immediate value as output parameter"),
-            // ParamMode::Relative if self.relative_base == 0 => arg_val as usize,
-            ParamMode::Relative => (self.relative_base + arg_val) as usize,
-        };
-        if out_ptr >= self.ram.len() {
-            self.ram.resize(out_ptr+1, 0);
+        if arg_ptr != addr && self.watchpoints.get(&arg_ptr).map_or(false, |w| w.read) {
+            self.watch_log.push(MemAccess { pc: self.pc, addr: arg_ptr, kind: Access::Fetch, value: self.ram[arg_ptr].clone() });
+        }
+        if self.watchpoints.get(&addr).map_or(false, |w| w.triggers(kind)) {
+            self.watch_log.push(MemAccess { pc: self.pc, addr, kind, value: value.clone() });
+        }
+    }
+
+    /// Decodes the instruction at the program counter, using the cache if allowed.
+    #[inline]
+    fn fetch(&mut self, cached: bool) -> Decoded {
+        let pc = self.pc;
+        let word = &self.ram[pc];
+        if !cached {
+            return Decoded::new(word);
+        }
+        match self.decoded.0.get(pc) {
+            Some(Some((w, d))) if w == word => *d,
+            _ => {
+                let d = Decoded::new(word);
+                // only low memory is cached, which is where programs keep their code
+                if pc < self.ram.dense().len() {
+                    if pc >= self.decoded.0.len() {
+                        self.decoded.0.resize(self.ram.dense().len(), None);
+                    }
+                    self.decoded.0[pc] = Some((word.clone(), d));
+                }
+                d
+            },
         }
-        &mut self.ram[out_ptr]
     }
 
     /// Steps the CPU once. Returns Some(_) if the computer needed to stop.
     /// Returns None if another instruction can be executed.
 	pub fn step<const DEBUG: bool>(&mut self) -> Option<RunResult> {
-		let pc = self.pc as usize;
-        let instr = Instruction(self.ram[pc]);
+        self.step_io::<DEBUG, dyn IntcodeIo<W>>(None, true)
+    }
+    /// Steps the CPU once, using `io` rather than the machine's own queues if supplied.
+    fn step_io<const DEBUG: bool, IO: IntcodeIo<W> + ?Sized>(&mut self, io: Option<&mut IO>, cached: bool) -> Option<RunResult> {
+        let (stepped, pc) = (self.stepped, self.pc);
+        let op = self.profile.is_some().then(|| Decoded::new(&self.ram[pc]).instr());
+        if let Some(trace) = &mut self.trace {
+            trace.steps.push(TraceStep { step: stepped, pc: self.pc, ..Default::default() });
+        }
+        if let Some(undo) = &mut self.undo {
+            undo.steps.push_back(UndoStep { pc: self.pc, relative_base: self.relative_base.clone(), ..Default::default() });
+        }
+        let rr = match self.exec::<DEBUG, IO>(io, cached) {
+            Ok(rr) => rr,
+            Err(fault) => Some(RunResult::Fault(fault)),
+        };
+        if self.stepped == stepped {
+            // the instruction didn't complete, so it had no effect worth recording
+            if let Some(trace) = &mut self.trace {
+                trace.steps.pop();
+            }
+            if let Some(undo) = &mut self.undo {
+                undo.steps.pop_back();
+            }
+        } else if let Some(undo) = &mut self.undo {
+            undo.complete();
+        }
+        if let (Some(profile), Some(op)) = (&mut self.profile, op) {
+            if self.stepped != stepped {
+                profile.record(pc, op, Some(self.pc));
+            } else if rr == Some(RunResult::Halted) {
+                profile.record(pc, op, None);
+            }
+        }
+        rr
+    }
+    fn exec<const DEBUG: bool, IO: IntcodeIo<W> + ?Sized>(&mut self, io: Option<&mut IO>, cached: bool) -> Result<Option<RunResult>, Fault> {
+        let instr = self.fetch(cached);
+        if let Some(step) = self.trace_step() {
+            step.op = instr.instr();
+        }
 
         if DEBUG {
-            eprintln!("PC:{:>2}    {:?}", self.pc, instr);
+            eprintln!("PC:{:>2}    {:?}", self.pc, Instruction(self.ram[self.pc].to_icint()));
         }
 
 		match instr.instr() {
 			99 => { // d2: hlt
-				return Some(RunResult::Halted);
+				return Ok(Some(RunResult::Halted));
 			},
 			1 => { // d2: add [a] [b] [out]
-                let in_a = self.resolve_param(instr, 0);
-                let in_b = self.resolve_param(instr, 1);
-                *self.resolve_param_mut(instr, 2) = in_a + in_b;
+                let in_a = self.resolve_param(instr, 0)?;
+                let in_b = self.resolve_param(instr, 1)?;
+                let sum = in_a.try_add(&in_b).ok_or(Fault::Overflow { pc: self.pc })?;
+                self.write_param(instr, 2, sum)?;
 				self.pc += 4;
 			},
 			2 => { // d2: mul [a] [b] [out]
-                let in_a = self.resolve_param(instr, 0);
-                let in_b = self.resolve_param(instr, 1);
-                *self.resolve_param_mut(instr, 2) = in_a * in_b;
+                let in_a = self.resolve_param(instr, 0)?;
+                let in_b = self.resolve_param(instr, 1)?;
+                let product = in_a.try_mul(&in_b).ok_or(Fault::Overflow { pc: self.pc })?;
+                self.write_param(instr, 2, product)?;
 				self.pc += 4;
 			},
             3 => { // d5: inp [out]
-                if self.input.len() == 0 {
-                    return Some(RunResult::Starved);
+                // a fault taking the input would lose it, so find where it goes first
+                let out_ptr = self.out_addr(instr, 0)?;
+                if !self.ram.can_write(out_ptr) {
+                    return Err(Fault::MemoryLimit { pc: self.pc, addr: out_ptr });
                 }
-                *self.resolve_param_mut(instr, 0) = self.input.remove(0);
+                let value = match io {
+                    Some(io) => io.read(),
+                    None => self.input.pop_front(),
+                };
+                let value = match value {
+                    Some(v) => v,
+                    None => return Ok(Some(RunResult::Starved)),
+                };
+                if let Some(step) = self.trace_step() {
+                    step.io = Some(IoEvent::Input(value.clone()));
+                }
+                if let Some(step) = self.undo_step() {
+                    step.input = Some(value.clone());
+                }
+                self.write_addr(0, out_ptr, value)?;
                 self.pc += 2;
             },
             4 => { // d5: out [a]
-                let in_a = self.resolve_param(instr, 0);
-                self.output.push(in_a);
+                let in_a = self.resolve_param(instr, 0)?;
+                if let Some(step) = self.trace_step() {
+                    step.io = Some(IoEvent::Output(in_a.clone()));
+                }
+                if let Some(step) = self.undo_step() {
+                    step.output = io.is_none();
+                }
+                match io {
+                    Some(io) => io.write(in_a),
+                    None => self.output.push_back(in_a),
+                }
                 self.pc += 2;
             },
             5 => { // d5: jnz [a] [tgt]
-                let in_a = self.resolve_param(instr, 0);
-                if in_a != 0 {
-                    let in_b = self.resolve_param(instr, 1);
-                    if in_b < 0 {
-                        panic!("attempt to set program counter to negative value ({})", in_b);
-                    }
-                    self.pc = in_b as usize;
+                let in_a = self.resolve_param(instr, 0)?;
+                if !in_a.is_zero() {
+                    let in_b = self.resolve_param(instr, 1)?;
+                    self.pc = self.jump_target(&in_b)?;
                 } else {
                     self.pc += 3;
                 }
             },
             6 => { // d5: jez [a] [tgt]
-                let in_a = self.resolve_param(instr, 0);
-                if in_a == 0 {
-                    let in_b = self.resolve_param(instr, 1);
-                    if in_b < 0 {
-                        panic!("attempt to set program counter to negative value ({})", in_b);
-                    }
-                    self.pc = in_b as usize;
+                let in_a = self.resolve_param(instr, 0)?;
+                if in_a.is_zero() {
+                    let in_b = self.resolve_param(instr, 1)?;
+                    self.pc = self.jump_target(&in_b)?;
                 } else {
                     self.pc += 3;
                 }
             },
             7 => { // d5: lt [a] [b] [out]
-                let in_a = self.resolve_param(instr, 0);
-                let in_b = self.resolve_param(instr, 1);
-                let out_ptr = self.resolve_param_mut(instr, 2);
-                *out_ptr = (in_a < in_b) as ICInt;
+                let in_a = self.resolve_param(instr, 0)?;
+                let in_b = self.resolve_param(instr, 1)?;
+                self.write_param(instr, 2, W::from_i64((in_a < in_b) as i64))?;
                 self.pc += 4;
             },
             8 => { // d5: eq [a] [b] [out]
-                let in_a = self.resolve_param(instr, 0);
-                let in_b = self.resolve_param(instr, 1);
-                let out_ptr = self.resolve_param_mut(instr, 2);
-                *out_ptr = (in_a == in_b) as ICInt;
+                let in_a = self.resolve_param(instr, 0)?;
+                let in_b = self.resolve_param(instr, 1)?;
+                self.write_param(instr, 2, W::from_i64((in_a == in_b) as i64))?;
                 self.pc += 4;
             },
             9 => { // d9: arb [a]
-                let in_a = self.resolve_param(instr, 0);
-                self.relative_base += in_a;
+                let in_a = self.resolve_param(instr, 0)?;
+                self.relative_base = self.relative_base.try_add(&in_a).ok_or(Fault::Overflow { pc: self.pc })?;
                 self.pc += 2;
             },
             _ => {
+                let instr = Instruction(self.ram[self.pc].to_icint());
                 eprintln!("unexpected opcode enountered @ PC={} after {} steps: {:?}", self.pc, self.stepped, instr);
-                return Some(RunResult::InvalidInstruction(instr));
+                return Ok(Some(RunResult::InvalidInstruction(instr)));
             },
 		}
 		self.stepped += 1;
@@ -238,38 +625,80 @@ impl Intcode {
                 let hash = self.hash_value();
                 // println!("hash: {}", hash);
                 if ! self.prev_states.insert(hash) {
-                    return Some(RunResult::RepeatedState);
+                    return Ok(Some(RunResult::RepeatedState));
                }
             }
         //     },
         //     _ => {},
         // }
-        None
+        Ok(None)
 	}
 
-    /// Runs until the computer needs to stop due to halting, needing input, or erroring.
-	fn run_inner<const DEBUG: bool>(&mut self) -> RunResult {
+    /// Converts a jump target from the program into a new program counter
+    fn jump_target(&self, target: &W) -> Result<usize, Fault> {
+        match target.to_usize() {
+            Some(pc) => Ok(pc),
+            None if target.is_negative() => Err(Fault::NegativeJump { pc: self.pc, target: target.to_icint() }),
+            None => Err(Fault::Overflow { pc: self.pc }),
+        }
+    }
+
+    /// Runs until the computer needs to stop due to halting, needing input, erroring, or
+    /// reaching one of the limits in `config`.
+	fn run_inner<const DEBUG: bool, IO: IntcodeIo<W> + ?Sized>(&mut self, mut io: Option<&mut IO>, config: &RunConfig) -> RunResult {
         let mut heatmap: HashMap<usize, usize> = HashMap::new();
+        let mut cycles = config.cycle_check.map(CycleDetector::new);
+        let mut steps = 0;
         loop {
-            let ent = heatmap.entry(self.pc).or_default();
-            if *ent > 10_000_000 {
-                panic!("instruction at {} exceeded 10M calls!", self.pc);
+            if config.max_steps.map_or(false, |max| steps >= max) {
+                return RunResult::OutOfSteps;
             }
-            *ent += 1;
-			if let Some(rr) = self.step::<DEBUG>() {
+            steps += 1;
+            if let Some(max) = config.max_hits_per_pc {
+                let ent = heatmap.entry(self.pc).or_default();
+                if *ent >= max {
+                    return RunResult::Fault(Fault::StepLimit { pc: self.pc });
+                }
+                *ent += 1;
+            }
+
+            // input read from `io` isn't part of the hashed state, so cycle detection starts
+            // over after it. Decoding to find out is only needed while checking for cycles.
+            let reads_io = cycles.is_some() && io.is_some() && Decoded::new(&self.ram[self.pc]).instr() == 3;
+			if let Some(rr) = self.step_io::<DEBUG, IO>(io.as_deref_mut(), config.decode_cache) {
                 return rr;
+            }
+            if let Some(det) = &mut cycles {
+                if reads_io {
+                    det.clear();
+                } else if det.repeated(|| self.hash_value()) {
+                    return RunResult::RepeatedState;
+                }
             }
 		}
 	}
     pub fn run(&mut self) -> RunResult {
-        self.run_inner::<false>()
+        let config = self.run_config;
+        self.run_inner::<false, dyn IntcodeIo<W>>(None, &config)
+    }
+    /// Runs using `io` for input and output, instead of the machine's own queues.
+    /// The machine starves once `io` has no more input.
+    pub fn run_io<IO: IntcodeIo<W> + ?Sized>(&mut self, io: &mut IO) -> RunResult {
+        let config = self.run_config;
+        self.run_inner::<false, IO>(Some(io), &config)
+    }
+    pub fn run_with(&mut self, config: &RunConfig) -> RunResult {
+        self.run_inner::<false, dyn IntcodeIo<W>>(None, config)
+    }
+    pub fn run_io_with<IO: IntcodeIo<W> + ?Sized>(&mut self, io: &mut IO, config: &RunConfig) -> RunResult {
+        self.run_inner::<false, IO>(Some(io), config)
     }
     #[cfg(test)]
     pub fn run_dbg(&mut self) -> RunResult {
-        self.run_inner::<true>()
+        self.run_inner::<true, dyn IntcodeIo<W>>(None, &RunConfig::default())
     }
 }
-impl Hash for Intcode {
+impl<W: Word> Hash for Intcode<W> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.pc.hash(state);
         self.relative_base.hash(state);
@@ -282,19 +711,161 @@ impl Hash for Intcode {
 fn test_for_input(prog: &[ICInt], inp: &[ICInt], exp: &[ICInt]) {
     let mut ic = Intcode::new(prog.to_vec());
     // ic.input.push(inp);
-    ic.input = inp.to_vec();
+    ic.input = inp.iter().copied().collect();
     assert_eq!(ic.run(), RunResult::Halted);
     // assert_eq!(ic.output.pop(), Some(exp), "computer did not produce expected output in test");
-    assert_eq!(&ic.output, exp, "computer did not produce expected output in test");
+    assert_eq!(ic.output, exp, "computer did not produce expected output in test");
 }
 #[cfg(test)]
 fn test_for_input_dbg(prog: &[ICInt], inp: &[ICInt], exp: &[ICInt]) {
     let mut ic = Intcode::new(prog.to_vec());
     // ic.input.push(inp);
-    ic.input = inp.to_vec();
+    ic.input = inp.iter().copied().collect();
     assert_eq!(ic.run_dbg(), RunResult::Halted);
     // assert_eq!(ic.output.pop(), Some(exp), "computer did not produce expected output in test");
-    assert_eq!(&ic.output, exp, "computer did not produce expected output in test");
+    assert_eq!(ic.output, exp, "computer did not produce expected output in test");
+}
+
+#[cfg(test)]
+mod faults {
+    use super::*;
+
+    fn fault_for(prog: &[ICInt]) -> RunResult {
+        Intcode::new(prog.to_vec()).run()
+    }
+
+    #[test]
+    fn negative_jump() {
+        assert_eq!(fault_for(&[1105,1,-5]), RunResult::Fault(Fault::NegativeJump { pc: 0, target: -5 }));
+        assert_eq!(fault_for(&[1106,0,-1]), RunResult::Fault(Fault::NegativeJump { pc: 0, target: -1 }));
+    }
+
+    #[test]
+    fn immediate_write() {
+        assert_eq!(fault_for(&[1101,1,1,0,11101,1,1,0,99]), RunResult::Fault(Fault::ImmediateWrite { pc: 4 }));
+        // the destination is checked before waiting for input
+        assert_eq!(fault_for(&[103,0,99]), RunResult::Fault(Fault::ImmediateWrite { pc: 0 }));
+    }
+
+    #[test]
+    fn bad_param_mode() {
+        assert_eq!(fault_for(&[301,0,0,0,99]), RunResult::Fault(Fault::BadParamMode { pc: 0, digit: 3 }));
+        assert_eq!(fault_for(&[104,7,904,0,99]), RunResult::Fault(Fault::BadParamMode { pc: 2, digit: 9 }));
+    }
+
+    #[test]
+    fn negative_address() {
+        assert_eq!(fault_for(&[1,-1,0,0,99]), RunResult::Fault(Fault::NegativeAddress { pc: 0, addr: -1 }));
+        assert_eq!(fault_for(&[109,-4,21101,1,1,1,99]), RunResult::Fault(Fault::NegativeAddress { pc: 2, addr: -3 }));
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut ic = Intcode::new(vec![1105,1,0]);
+        let config = RunConfig { max_hits_per_pc: Some(10_000_000), ..Default::default() };
+        assert_eq!(ic.run_with(&config), RunResult::Fault(Fault::StepLimit { pc: 0 }));
+    }
+
+    #[test]
+    fn memory_limit() {
+        let prog = vec![1101,5,0,1_000_000_000,4,1_000_000_000,99];
+        let mut ic = Intcode::new(prog.clone());
+        assert_eq!(ic.run(), RunResult::Halted);
+        assert_eq!(ic.output, vec![5]);
+        assert_eq!(ic.ram.dense().len(), prog.len(), "high memory is paged");
+
+        let mut ic = Intcode::new(prog);
+        ic.ram.set_page_limit(0);
+        assert_eq!(ic.run(), RunResult::Fault(Fault::MemoryLimit { pc: 0, addr: 1_000_000_000 }));
+    }
+
+    #[test]
+    fn faulted_input_is_kept() {
+        let mut ic = Intcode::new(vec![103,0,99]);
+        ic.input.push_back(7);
+        assert_eq!(ic.run(), RunResult::Fault(Fault::ImmediateWrite { pc: 0 }));
+        assert_eq!(ic.input, vec![7]);
+
+        // reads into high memory, then outputs what it read
+        let mut ic = Intcode::new(vec![3,1_000_000_000,4,1_000_000_000,99]);
+        ic.ram.set_page_limit(0);
+        ic.input.push_back(7);
+        assert_eq!(ic.run(), RunResult::Fault(Fault::MemoryLimit { pc: 0, addr: 1_000_000_000 }));
+        assert_eq!(ic.input, vec![7]);
+        let mut reads = 0;
+        let mut io = io::FnIo::new(|| { reads += 1; Some(8) }, |_| {});
+        assert_eq!(ic.run_io(&mut io), RunResult::Fault(Fault::MemoryLimit { pc: 0, addr: 1_000_000_000 }));
+        assert_eq!(reads, 0, "nothing is read from io");
+
+        // resuming once there's memory reads the same input
+        ic.ram.set_page_limit(1);
+        assert_eq!(ic.run(), RunResult::Halted);
+        assert_eq!(ic.output, vec![7]);
+    }
+}
+
+#[cfg(test)]
+mod limits {
+    use super::*;
+    use io::FnIo;
+
+    /// Flips `x` between 0 and 1 forever, so its state repeats every 2 iterations
+    const FLIP: &str = "
+        loop: eq  [x], #0, [x]
+              jnz #1, #loop
+        x: db 0
+    ";
+
+    #[test]
+    fn step_budget() {
+        let mut ic = Intcode::new(vec![1105,1,0]);
+        let config = RunConfig { max_steps: Some(100), max_hits_per_pc: None, ..Default::default() };
+        assert_eq!(ic.run_with(&config), RunResult::OutOfSteps);
+        assert_eq!(ic.stepped(), 100);
+        assert_eq!(ic.run_with(&config), RunResult::OutOfSteps, "budget applies to each run");
+        assert_eq!(ic.stepped(), 200);
+
+        let config = RunConfig { max_hits_per_pc: Some(5), ..config };
+        assert_eq!(ic.run_with(&config), RunResult::Fault(Fault::StepLimit { pc: 0 }));
+        assert_eq!(ic.stepped(), 205);
+    }
+
+    #[test]
+    fn cycles() {
+        for check in [CycleCheck::Brent, CycleCheck::Periodic(1), CycleCheck::Periodic(7)] {
+            let mut ic = Intcode::assemble(FLIP).unwrap();
+            let config = RunConfig { cycle_check: Some(check), max_steps: Some(1000), ..Default::default() };
+            assert_eq!(ic.run_with(&config), RunResult::RepeatedState, "{:?}", check);
+            assert!(ic.stepped() <= 5 * 7, "{:?} took {} steps", check, ic.stepped());
+        }
+
+        // counting never repeats a state
+        let mut ic = Intcode::assemble("loop: add [x], #1, [x]\n jnz #1, #loop\n x: db 0").unwrap();
+        let config = RunConfig { cycle_check: Some(CycleCheck::Brent), max_steps: Some(1000), ..Default::default() };
+        assert_eq!(ic.run_with(&config), RunResult::OutOfSteps);
+    }
+
+    #[test]
+    fn cycles_between_inputs() {
+        // the same state after every input isn't a loop, since the input may change
+        let mut ic = Intcode::assemble("loop: inp [x]\n jnz #1, #loop\n x: db 0").unwrap();
+        let config = RunConfig { cycle_check: Some(CycleCheck::Brent), max_steps: Some(1000), ..Default::default() };
+        let mut io = FnIo::new(|| Some(0), |_| {});
+        assert_eq!(ic.run_io_with(&mut io, &config), RunResult::OutOfSteps);
+
+        // but with its own queue, the queue is part of the state
+        ic.input.extend([0; 20]);
+        assert_eq!(ic.run_with(&config), RunResult::Starved);
+    }
+
+    #[test]
+    fn reset_forgets_states() {
+        let mut ic = Intcode::assemble(FLIP).unwrap();
+        let stepped = |ic: &mut Intcode| (1..).find(|_| ic.step::<true>().is_some()).unwrap();
+        let first = stepped(&mut ic);
+        ic.reset();
+        assert_eq!(stepped(&mut ic), first);
+    }
 }
 
 #[cfg(test)]
@@ -402,9 +973,9 @@ mod day09 {
 
         let mut ic = Intcode::new(PROGRAM.to_vec());
         assert_eq!(ic.run(), RunResult::Halted);
-        let out = ic.output.remove(0);
+        let out = ic.output.pop_front().unwrap();
         assert_eq!((out as f32).log10().round(), 15.0, "expected 16-digit number, got {:?}", {
-            ic.output.insert(0, out);
+            ic.output.push_front(out);
             ic.output
         });
     }
@@ -0,0 +1,122 @@
+//! Text-based interaction with Intcode programs.
+//!
+//! Several puzzle programs talk in ASCII: they print prompts and maps a character at a time and
+//! read commands as lines of character codes. An [`AsciiConsole`] wraps a machine to do the
+//! conversion in both directions. Anything the program outputs that isn't ASCII - usually the
+//! puzzle's answer, printed once the text has served its purpose - is kept apart from the text.
+
+use std::collections::VecDeque;
+
+use super::{ICInt, Intcode, RunConfig, RunResult};
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AsciiConsole {
+    pub ic: Intcode,
+    /// Lines of text output, without their line feeds
+    pub lines: VecDeque<String>,
+    /// Output since the last line feed, such as a prompt
+    pub partial: String,
+    /// Output values outside of the ASCII range, in the order they were output
+    pub values: Vec<ICInt>,
+}
+impl AsciiConsole {
+    pub fn new(ic: Intcode) -> AsciiConsole {
+        AsciiConsole { ic, ..Default::default() }
+    }
+
+    /// Queues the characters of `text` as input, without adding a line feed
+    pub fn send(&mut self, text: &str) {
+        self.ic.input.extend(text.bytes().map(ICInt::from));
+    }
+    /// Queues a line of input, adding the line feed that ends it
+    pub fn send_line(&mut self, line: &str) {
+        self.send(line);
+        self.ic.input.push_back(ICInt::from(b'\n'));
+    }
+
+    /// Runs the machine until it stops, sorting its output into text and other values
+    pub fn run(&mut self) -> RunResult {
+        let config = self.ic.run_config;
+        self.run_with(&config)
+    }
+    pub fn run_with(&mut self, config: &RunConfig) -> RunResult {
+        let rr = self.ic.run_with(config);
+        for v in self.ic.output.drain(..) {
+            match u8::try_from(v) {
+                Ok(b'\n') => self.lines.push_back(std::mem::take(&mut self.partial)),
+                Ok(b) if b.is_ascii() => self.partial.push(char::from(b)),
+                _ => self.values.push(v),
+            }
+        }
+        rr
+    }
+    /// Sends a line of input and runs the machine until it stops again, such as when it
+    /// wants the next command
+    pub fn command(&mut self, line: &str) -> RunResult {
+        self.send_line(line);
+        self.run()
+    }
+
+    /// Removes and returns every complete line of output
+    pub fn take_lines(&mut self) -> Vec<String> {
+        self.lines.drain(..).collect()
+    }
+    /// Removes and returns all of the text output, including any unfinished line
+    pub fn take_text(&mut self) -> String {
+        let mut text = String::new();
+        for line in self.lines.drain(..) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text.push_str(&std::mem::take(&mut self.partial));
+        text
+    }
+    /// Removes and returns the output values that weren't text
+    pub fn take_values(&mut self) -> Vec<ICInt> {
+        std::mem::take(&mut self.values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversation() {
+        // prompts for a line, echoes it back and outputs its length as a raw value once it's
+        // longer than 3 characters
+        let ic = Intcode::assemble("
+            prompt: out #62
+                    add #0, #0, [len]
+            loop:   inp [c]
+                    out [c]
+                    eq  [c], #10, [t]
+                    jnz [t], #eol
+                    add [len], #1, [len]
+                    jnz #1, #loop
+            eol:    lt  #3, [len], [t]
+                    jez [t], #prompt
+                    add [len], #1000, [len]
+                    out [len]
+                    jnz #1, #prompt
+            c:      db 0
+            t:      db 0
+            len:    db 0
+        ").unwrap();
+        let mut con = AsciiConsole::new(ic);
+        assert_eq!(con.run(), RunResult::Starved);
+        assert_eq!((con.lines.len(), con.partial.as_str()), (0, ">"));
+
+        assert_eq!(con.command("hi"), RunResult::Starved);
+        con.send("hello");
+        assert_eq!(con.command(" world"), RunResult::Starved);
+        assert_eq!(con.take_lines(), vec![">hi", ">hello world"]);
+        assert_eq!(con.take_values(), vec![1011]);
+        assert_eq!(con.partial, ">");
+
+        con.send("abc");
+        assert_eq!(con.run(), RunResult::Starved);
+        assert_eq!(con.take_text(), ">abc");
+        assert!(con.take_values().is_empty());
+    }
+}
@@ -0,0 +1,426 @@
+//! Assembler for Intcode programs, using the same syntax the disassembler produces.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! start:  inp [value]           ; position mode
+//!         arb #16               ; immediate mode
+//!         mul [value], #-1, rb+0 ; relative mode (also `rb`, `rb-N`)
+//!         jnz rb+0, #start
+//!         hlt
+//! value:  db 0, 1, start+2, "text"
+//! ```
+//!
+//! Operand values may be integers, labels, or a label with an offset. A statement may be
+//! prefixed by the address it is expected at (as in a disassembly listing), which is checked.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::disasm::{Decoded, Op, Operand};
+use super::{ICInt, Intcode};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    OperandCount { op: Op, expected: usize, found: usize },
+    BadOperand(String),
+    BadLabel(String),
+    ImmediateOutput(Op),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    AddressMismatch { expected: usize, actual: usize },
+    UnterminatedString,
+}
+
+/// An error encountered while assembling, along with the 1-based line it occurred on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic(m) => write!(f, "unknown mnemonic {:?}", m),
+            AsmErrorKind::OperandCount { op, expected, found } => write!(f, "{} takes {} operands, found {}", op, expected, found),
+            AsmErrorKind::BadOperand(o) => write!(f, "unable to parse operand {:?}", o),
+            AsmErrorKind::BadLabel(l) => write!(f, "invalid label name {:?}", l),
+            AsmErrorKind::ImmediateOutput(op) => write!(f, "output operand of {} cannot be immediate", op),
+            AsmErrorKind::DuplicateLabel(l) => write!(f, "label {:?} defined more than once", l),
+            AsmErrorKind::UndefinedLabel(l) => write!(f, "label {:?} is not defined", l),
+            AsmErrorKind::AddressMismatch { expected, actual } => write!(f, "statement expected at address {}, but assembled at {}", expected, actual),
+            AsmErrorKind::UnterminatedString => write!(f, "unterminated string literal"),
+        }
+    }
+}
+impl std::error::Error for AsmError {}
+
+/// A value that may refer to a label, resolved once all labels are known
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Int(ICInt),
+    Label(String, ICInt),
+}
+impl Expr {
+    fn parse(s: &str) -> Option<Expr> {
+        let s = s.trim();
+        if let Ok(n) = s.parse() {
+            return Some(Expr::Int(n));
+        }
+        let (name, offset) = match s.find(['+', '-']) {
+            Some(i) => {
+                let off: ICInt = s[i+1..].trim().parse().ok()?;
+                (s[..i].trim(), if &s[i..i+1] == "-" { -off } else { off })
+            },
+            None => (s, 0),
+        };
+        valid_label(name).then(|| Expr::Label(name.to_owned(), offset))
+    }
+    fn resolve(&self, labels: &HashMap<&str, usize>) -> Result<ICInt, AsmErrorKind> {
+        match self {
+            Expr::Int(n) => Ok(*n),
+            Expr::Label(name, off) => labels.get(name.as_str())
+                .map(|&addr| addr as ICInt + off)
+                .ok_or_else(|| AsmErrorKind::UndefinedLabel(name.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Arg {
+    Position(Expr),
+    Immediate(Expr),
+    Relative(Expr),
+}
+impl Arg {
+    fn parse(s: &str) -> Option<Arg> {
+        let s = s.trim();
+        if let Some(inner) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            Expr::parse(inner).map(Arg::Position)
+        } else if let Some(imm) = s.strip_prefix('#') {
+            Expr::parse(imm).map(Arg::Immediate)
+        } else if let Some(rel) = s.strip_prefix("rb") {
+            let rel = rel.trim_start();
+            if rel.is_empty() {
+                Some(Arg::Relative(Expr::Int(0)))
+            } else if let Some(off) = rel.strip_prefix('+') {
+                Expr::parse(off).map(Arg::Relative)
+            } else if rel.starts_with('-') {
+                match Expr::parse(rel)? {
+                    Expr::Int(n) => Some(Arg::Relative(Expr::Int(n))),
+                    Expr::Label(..) => None,
+                }
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+    fn resolve(&self, labels: &HashMap<&str, usize>) -> Result<Operand, AsmErrorKind> {
+        Ok(match self {
+            Arg::Position(e) => Operand::Position(e.resolve(labels)?),
+            Arg::Immediate(e) => Operand::Immediate(e.resolve(labels)?),
+            Arg::Relative(e) => Operand::Relative(e.resolve(labels)?),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Instr(Op, Vec<Arg>),
+    Data(Vec<Expr>),
+}
+impl Stmt {
+    fn len(&self) -> usize {
+        match self {
+            Stmt::Instr(_, args) => 1 + args.len(),
+            Stmt::Data(values) => values.len(),
+        }
+    }
+}
+
+fn valid_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && s != "rb"
+}
+
+/// Splits a comma separated operand list, keeping string literals intact.
+/// Also strips any trailing comment.
+fn split_operands(s: &str) -> Result<Vec<&str>, AsmErrorKind> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_str = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_str => escaped = true,
+            '"' => in_str = !in_str,
+            ',' if !in_str => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            },
+            ';' if !in_str => {
+                parts.push(s[start..i].trim());
+                return Ok(parts);
+            },
+            _ => {},
+        }
+    }
+    if in_str {
+        return Err(AsmErrorKind::UnterminatedString);
+    }
+    parts.push(s[start..].trim());
+    Ok(parts)
+}
+
+/// Parses a string literal into its ASCII codes
+fn parse_string(s: &str) -> Option<Vec<Expr>> {
+    let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut values = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                c @ ('\\' | '"') => c,
+                _ => return None,
+            },
+            c => c,
+        };
+        values.push(Expr::Int(c as ICInt));
+    }
+    Some(values)
+}
+
+fn parse_stmt(mnemonic: &str, rest: &str) -> Result<Stmt, AsmErrorKind> {
+    let operands = split_operands(rest)?;
+    let operands: Vec<&str> = if operands.len() == 1 && operands[0].is_empty() { Vec::new() } else { operands };
+
+    if mnemonic == "db" {
+        let mut values = Vec::new();
+        for opnd in operands {
+            match parse_string(opnd) {
+                Some(chars) => values.extend(chars),
+                None => values.push(Expr::parse(opnd).ok_or_else(|| AsmErrorKind::BadOperand(opnd.to_owned()))?),
+            }
+        }
+        return Ok(Stmt::Data(values));
+    }
+
+    let op = Op::from_mnemonic(mnemonic).ok_or_else(|| AsmErrorKind::UnknownMnemonic(mnemonic.to_owned()))?;
+    if operands.len() != op.arity() {
+        return Err(AsmErrorKind::OperandCount { op, expected: op.arity(), found: operands.len() });
+    }
+    let args = operands.iter()
+        .map(|o| Arg::parse(o).ok_or_else(|| AsmErrorKind::BadOperand(o.to_string())))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(Arg::Immediate(_)) = op.output_param().map(|i| &args[i]) {
+        return Err(AsmErrorKind::ImmediateOutput(op));
+    }
+    Ok(Stmt::Instr(op, args))
+}
+
+/// Assembles source text into a program suitable for [`Intcode::new`]
+pub fn assemble(src: &str) -> Result<Vec<ICInt>, AsmError> {
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut stmts: Vec<(usize, Stmt)> = Vec::new();
+    let mut addr = 0;
+
+    for (lineno, line) in src.lines().enumerate() {
+        let err = |kind| AsmError { line: lineno + 1, kind };
+        let mut line = line.trim_start();
+
+        // leading labels
+        while let Some(colon) = line.find(':') {
+            let name = line[..colon].trim();
+            if name.contains([';', '"', ' ', '\t']) {
+                break;
+            }
+            if !valid_label(name) {
+                return Err(err(AsmErrorKind::BadLabel(name.to_owned())));
+            }
+            if labels.insert(name, addr).is_some() {
+                return Err(err(AsmErrorKind::DuplicateLabel(name.to_owned())));
+            }
+            line = line[colon+1..].trim_start();
+        }
+
+        let mut words = line.splitn(2, char::is_whitespace);
+        let mut mnemonic = words.next().unwrap_or("");
+        let mut rest = words.next().unwrap_or("");
+
+        // optional address check, as in a disassembly listing
+        if let Ok(expected) = mnemonic.parse::<usize>() {
+            if expected != addr {
+                return Err(err(AsmErrorKind::AddressMismatch { expected, actual: addr }));
+            }
+            let mut words = rest.trim_start().splitn(2, char::is_whitespace);
+            mnemonic = words.next().unwrap_or("");
+            rest = words.next().unwrap_or("");
+        }
+
+        if mnemonic.is_empty() || mnemonic.starts_with(';') {
+            continue;
+        }
+        let stmt = parse_stmt(mnemonic, rest).map_err(err)?;
+        let len = stmt.len();
+        stmts.push((lineno + 1, stmt));
+        addr += len;
+    }
+
+    let mut program = Vec::with_capacity(addr);
+    for (line, stmt) in stmts {
+        let err = |kind| AsmError { line, kind };
+        match stmt {
+            Stmt::Instr(op, args) => {
+                let operands = args.iter()
+                    .map(|a| a.resolve(&labels))
+                    .collect::<Result<_, _>>()
+                    .map_err(err)?;
+                let decoded = Decoded { addr: program.len(), op, operands };
+                program.extend(decoded.encode());
+            },
+            Stmt::Data(values) => {
+                for v in values {
+                    program.push(v.resolve(&labels).map_err(err)?);
+                }
+            },
+        }
+    }
+    Ok(program)
+}
+
+impl Intcode {
+    /// Creates a machine from assembly source
+    pub fn assemble(src: &str) -> Result<Intcode, AsmError> {
+        assemble(src).map(Intcode::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::RunResult;
+
+    #[test]
+    fn encoding() {
+        // day05's equal-to-8 example
+        let prog = assemble("
+            inp [9]
+            eq  [9], [10], [9]
+            out [9]
+            hlt
+            db  -1, 8
+        ").unwrap();
+        assert_eq!(prog, vec![3,9,8,9,10,9,4,9,99,-1,8]);
+
+        assert_eq!(assemble("mul [4], #3, rb+4").unwrap(), vec![21002,4,3,4]);
+        assert_eq!(assemble("add rb, rb-1, rb+2").unwrap(), vec![22201,0,-1,2]);
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let prog = assemble(r#"
+                jnz #1, #main     ; forward reference
+            msg: db "hi\n", 0
+            main:
+                out [msg+1]
+            end: hlt
+                db end, msg-1
+        "#).unwrap();
+        assert_eq!(prog, vec![1105,1,7, 104,105,10,0, 4,4, 99, 9,2]);
+    }
+
+    #[test]
+    fn errors() {
+        let kind = |src| assemble(src).unwrap_err().kind;
+        assert_eq!(kind("mov [1], [2]"), AsmErrorKind::UnknownMnemonic("mov".into()));
+        assert_eq!(kind("add [1], [2]"), AsmErrorKind::OperandCount { op: Op::Add, expected: 3, found: 2 });
+        assert_eq!(kind("out (1)"), AsmErrorKind::BadOperand("(1)".into()));
+        assert_eq!(kind("inp #4"), AsmErrorKind::ImmediateOutput(Op::Inp));
+        assert_eq!(kind("a: hlt\na: hlt"), AsmErrorKind::DuplicateLabel("a".into()));
+        assert_eq!(kind("jnz #1, #nowhere"), AsmErrorKind::UndefinedLabel("nowhere".into()));
+        assert_eq!(kind("0 hlt\n2 hlt"), AsmErrorKind::AddressMismatch { expected: 2, actual: 1 });
+        assert_eq!(kind("db \"abc"), AsmErrorKind::UnterminatedString);
+        assert_eq!(assemble("hlt\n\n  out rb+0, #1").unwrap_err().line, 3);
+    }
+
+    #[test]
+    fn listing_roundtrip() {
+        for day in [aoch::daystr!("09"), aoch::daystr!("13"), aoch::daystr!("25")] {
+            let ic = Intcode::parse(day);
+            let listing = ic.disassemble().to_string();
+            assert_eq!(assemble(&listing).unwrap(), ic.ram.dense());
+        }
+    }
+
+    #[test]
+    fn starved() {
+        let mut ic = Intcode::assemble("
+            inp [a]
+            inp [b]
+            add [a], [b], [a]
+            out [a]
+            hlt
+            a: db 0
+            b: db 0
+        ").unwrap();
+
+        assert_eq!(ic.run(), RunResult::Starved);
+        assert_eq!(ic.pc(), 0, "starving should not advance the program counter");
+        ic.input.push_back(3);
+        assert_eq!(ic.run(), RunResult::Starved);
+        assert_eq!(ic.pc(), 2);
+        assert_eq!(ic.run(), RunResult::Starved, "rerunning while starved is a no-op");
+        ic.input.push_back(4);
+        assert_eq!(ic.run(), RunResult::Halted);
+        assert_eq!(ic.output, vec![7]);
+        assert_eq!(ic.run(), RunResult::Halted, "rerunning while halted is a no-op");
+    }
+
+    #[test]
+    fn repeated_state() {
+        let mut ic = Intcode::assemble("
+            spin: jnz #1, #spin
+        ").unwrap();
+        assert_eq!(ic.run_dbg(), RunResult::RepeatedState);
+
+        // a counting loop never repeats a state, even though the program counter does
+        let mut ic = Intcode::assemble("
+            loop: add [n], #1, [n]
+                  lt  [n], #5, [c]
+                  jnz [c], #loop
+                  out [n]
+                  hlt
+            n: db 0
+            c: db 0
+        ").unwrap();
+        assert_eq!(ic.run_dbg(), RunResult::Halted);
+        assert_eq!(ic.output, vec![5]);
+    }
+
+    #[test]
+    fn relative_base() {
+        // the base accumulates across arb instructions and can be addressed with negative offsets
+        let mut ic = Intcode::assemble("
+            arb #100
+            add #5, #0, rb+0
+            arb #-3
+            add rb+3, #1, rb-1
+            arb [delta]
+            out rb-3
+            out rb+1
+            hlt
+            delta: db 2
+        ").unwrap();
+        assert_eq!(ic.run(), RunResult::Halted);
+        assert_eq!(ic.output, vec![6, 5]);
+        assert_eq!(ic.ram[96], 6);
+    }
+}
@@ -0,0 +1,318 @@
+//! Control-flow graphs of Intcode programs.
+//!
+//! The graph is built on top of the disassembler's split between code and data. A basic block
+//! ends at every jump and `hlt`, and before every jump target or return address. Jumps to an
+//! immediate target give edges, while jumps through memory - which is how the puzzle programs
+//! return from functions - can't be followed statically and are flagged instead.
+//!
+//! Calls follow the convention described in [`disasm`](super::disasm): a return address is
+//! pushed to `rb+0` and the jump that follows enters the function. Each call target starts a
+//! [`Function`], made up of the blocks reachable from it without following calls, and the
+//! caller continues at the return address. Functions usually begin by moving the relative base
+//! up past their stack frame with `arb`, which gives the frame size.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+use super::disasm::{self, Decoded, Label, Op};
+use super::{ICInt, Intcode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction in memory
+    Fallthrough,
+    /// A jump to an immediate target
+    Jump,
+    /// A function call
+    Call,
+    /// From a call site to its return address, standing in for the function's return
+    Return,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Edge {
+    /// Start address of the block the edge leaves
+    pub from: usize,
+    /// Start address of the block the edge enters
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub instructions: Vec<Decoded>,
+    /// True if the block ends with a jump whose target is read from memory
+    pub indirect: bool,
+}
+impl Block {
+    pub fn start(&self) -> usize {
+        self.instructions[0].addr
+    }
+    /// One past the last address of the block
+    pub fn end(&self) -> usize {
+        self.instructions.last().unwrap().next()
+    }
+    pub fn last(&self) -> &Decoded {
+        self.instructions.last().unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub entry: usize,
+    /// Start addresses of the blocks reachable from the entry without following calls
+    pub blocks: BTreeSet<usize>,
+    /// Entry points of the functions this one calls
+    pub calls: BTreeSet<usize>,
+    /// Amount the function moves the relative base by on entry
+    pub frame: Option<ICInt>,
+    /// True if the function contains an indirect jump, such as its return
+    pub returns: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    /// Basic blocks by start address
+    pub blocks: BTreeMap<usize, Block>,
+    pub edges: BTreeSet<Edge>,
+    /// Functions by entry point. The program's entry at address 0 is treated as a function.
+    pub functions: BTreeMap<usize, Function>,
+}
+
+/// If the block ends by calling a function, returns the function's entry (if it's an immediate)
+/// and the return address.
+fn call(ram: &[ICInt], block: &Block) -> Option<(Option<usize>, usize)> {
+    let (push, jump) = match &block.instructions[..] {
+        [.., push, jump] => (push, jump),
+        _ => return None,
+    };
+    let ret = disasm::call_return(ram, push)?;
+    let target = jump.jump_target().and_then(|t| usize::try_from(t).ok());
+    Some((target, ret))
+}
+
+impl Cfg {
+    /// Builds the control-flow graph of a program, using reachability from address 0 to
+    /// separate code from data.
+    pub fn new(ram: &[ICInt]) -> Cfg {
+        let listing = disasm::disassemble(ram);
+        let code: BTreeMap<usize, &Decoded> = listing.instructions().map(|d| (d.addr, d)).collect();
+
+        let mut leaders: BTreeSet<usize> = listing.labels.clone();
+        leaders.insert(0);
+        for d in code.values() {
+            if matches!(d.op, Op::Jnz | Op::Jez | Op::Hlt) {
+                leaders.insert(d.next());
+            }
+        }
+
+        let mut blocks: BTreeMap<usize, Block> = BTreeMap::new();
+        let mut current: Option<Block> = None;
+        for (&addr, &d) in &code {
+            let contiguous = current.as_ref().map_or(false, |b| b.end() == addr);
+            if !contiguous || leaders.contains(&addr) {
+                if let Some(b) = current.take() {
+                    blocks.insert(b.start(), b);
+                }
+            }
+            let block = current.get_or_insert_with(|| Block { instructions: Vec::new(), indirect: false });
+            block.instructions.push(d.clone());
+        }
+        if let Some(b) = current {
+            blocks.insert(b.start(), b);
+        }
+
+        let mut edges = BTreeSet::new();
+        let mut entries = BTreeSet::from([0]);
+        for (&start, block) in &mut blocks {
+            let mut edge = |to: usize, kind| edges.insert(Edge { from: start, to, kind });
+            if let Some((target, ret)) = call(ram, block) {
+                if let Some(target) = target.filter(|t| code.contains_key(t)) {
+                    edge(target, EdgeKind::Call);
+                    entries.insert(target);
+                }
+                edge(ret, EdgeKind::Return);
+                block.indirect = target.is_none();
+                continue;
+            }
+            let last = block.last();
+            let mut indirect = false;
+            if last.may_jump() {
+                match last.jump_target().and_then(|t| usize::try_from(t).ok()) {
+                    Some(target) if code.contains_key(&target) => { edge(target, EdgeKind::Jump); },
+                    // a jump to an immediate outside of the code would fault or run data
+                    Some(_) => {},
+                    None => indirect = true,
+                }
+            }
+            if last.falls_through() && code.contains_key(&last.next()) {
+                edge(last.next(), EdgeKind::Fallthrough);
+            }
+            block.indirect = indirect;
+        }
+
+        let mut functions = BTreeMap::new();
+        for &entry in &entries {
+            let mut func = Function {
+                entry,
+                blocks: BTreeSet::new(),
+                calls: BTreeSet::new(),
+                frame: blocks.get(&entry)
+                    .map(|b| &b.instructions[0])
+                    .filter(|d| d.op == Op::Arb)
+                    .and_then(|d| d.operands[0].imm()),
+                returns: false,
+            };
+            let mut pending = vec![entry];
+            while let Some(b) = pending.pop() {
+                if !func.blocks.insert(b) {
+                    continue;
+                }
+                func.returns |= blocks[&b].indirect;
+                for e in edges.range(Edge { from: b, to: 0, kind: EdgeKind::Fallthrough }..) {
+                    if e.from != b {
+                        break;
+                    }
+                    match e.kind {
+                        EdgeKind::Call => { func.calls.insert(e.to); },
+                        _ => pending.push(e.to),
+                    }
+                }
+            }
+            functions.insert(entry, func);
+        }
+
+        Cfg { blocks, edges, functions }
+    }
+
+    /// Returns the block containing `addr`, if it is code
+    pub fn block_containing(&self, addr: usize) -> Option<&Block> {
+        let (_, block) = self.blocks.range(..=addr).next_back()?;
+        (addr < block.end()).then(|| block)
+    }
+    /// Addresses of every jump whose target is read from memory
+    pub fn indirect_jumps(&self) -> impl Iterator<Item = usize> + '_ {
+        self.blocks.values().filter(|b| b.indirect).map(|b| b.last().addr)
+    }
+
+    /// Renders the graph in Graphviz's DOT language, grouping blocks into a cluster per
+    /// function. Indirect jumps are drawn in red, and calls and returns are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        self.write_dot(&mut dot).unwrap();
+        dot
+    }
+    fn write_dot<W: Write>(&self, f: &mut W) -> fmt::Result {
+        writeln!(f, "digraph intcode {{")?;
+        writeln!(f, "    node [shape=box, fontname=\"monospace\"];")?;
+        // a block shared by several functions is drawn in the first one
+        let mut placed = BTreeSet::new();
+        for func in self.functions.values() {
+            writeln!(f, "    subgraph cluster_{} {{", func.entry)?;
+            match func.frame {
+                Some(frame) => writeln!(f, "        label=\"{} (frame {})\";", Label(func.entry), frame)?,
+                None => writeln!(f, "        label=\"{}\";", Label(func.entry))?,
+            }
+            for &b in func.blocks.iter().filter(|&&b| placed.insert(b)) {
+                self.write_block(f, &self.blocks[&b])?;
+            }
+            writeln!(f, "    }}")?;
+        }
+        for block in self.blocks.values().filter(|b| !placed.contains(&b.start())) {
+            self.write_block(f, block)?;
+        }
+        for e in &self.edges {
+            let style = match e.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Jump => " [color=blue]",
+                EdgeKind::Call => " [style=dashed, label=\"call\"]",
+                EdgeKind::Return => " [style=dotted]",
+            };
+            writeln!(f, "    b{} -> b{}{};", e.from, e.to, style)?;
+        }
+        writeln!(f, "}}")
+    }
+    fn write_block<W: Write>(&self, f: &mut W, block: &Block) -> fmt::Result {
+        write!(f, "        b{} [label=\"{}:\\l", block.start(), Label(block.start()))?;
+        for d in &block.instructions {
+            write!(f, "{:04}  {}\\l", d.addr, d)?;
+        }
+        write!(f, "\"")?;
+        if block.indirect {
+            write!(f, ", color=red")?;
+        }
+        writeln!(f, "];")
+    }
+}
+
+impl Intcode {
+    /// Builds the control-flow graph of the machine's current memory
+    pub fn cfg(&self) -> Cfg {
+        Cfg::new(self.ram.dense())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_and_calls() {
+        let ic = Intcode::assemble("
+                    arb #100
+                    inp [n]
+            again:  add #back, #0, rb+0
+                    jnz #1, #double
+            back:   jez [n], #done
+                    add [n], #-1, [n]
+                    jnz #1, #again
+            done:   hlt
+            double: arb #2
+                    mul rb-1, #2, rb-1
+                    arb #-2
+                    jez #0, rb+0
+            n:      db 0
+        ").unwrap();
+        let cfg = ic.cfg();
+        let starts: Vec<_> = cfg.blocks.keys().copied().collect();
+        assert_eq!(starts, vec![0, 4, 11, 14, 21, 22]);
+        assert_eq!(cfg.blocks[&4].instructions.len(), 2);
+        assert_eq!(cfg.block_containing(16).map(Block::start), Some(14));
+        assert_eq!(cfg.block_containing(33), None, "data");
+
+        let edges: Vec<_> = cfg.edges.iter().map(|e| (e.from, e.to, e.kind)).collect();
+        assert_eq!(edges, vec![
+            (0, 4, EdgeKind::Fallthrough),
+            (4, 11, EdgeKind::Return),
+            (4, 22, EdgeKind::Call),
+            (11, 14, EdgeKind::Fallthrough),
+            (11, 21, EdgeKind::Jump),
+            (14, 4, EdgeKind::Jump),
+        ]);
+        assert_eq!(cfg.indirect_jumps().collect::<Vec<_>>(), vec![30]);
+
+        assert_eq!(cfg.functions.keys().copied().collect::<Vec<_>>(), vec![0, 22]);
+        let main = &cfg.functions[&0];
+        assert_eq!(main.blocks, BTreeSet::from([0, 4, 11, 14, 21]));
+        assert_eq!(main.calls, BTreeSet::from([22]));
+        assert!(!main.returns);
+        let double = &cfg.functions[&22];
+        assert_eq!((double.frame, double.returns), (Some(2), true));
+
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("    subgraph cluster_22 {\n        label=\"L0022 (frame 2)\";\n"), "{}", dot);
+        assert!(dot.contains("    b4 -> b22 [style=dashed, label=\"call\"];\n"));
+        assert!(dot.contains("0030  jez #0, rb+0\\l\", color=red];"));
+    }
+
+    #[test]
+    fn puzzle_program() {
+        // day 13 draws each tile through a function at 578, called from the loop at 37
+        let cfg = Intcode::parse(aoch::daystr!("13")).cfg();
+        let draw = &cfg.functions[&578];
+        assert!(draw.returns);
+        assert!(cfg.functions[&0].calls.contains(&578));
+        assert!(cfg.edges.contains(&Edge { from: cfg.block_containing(31).unwrap().start(), to: 37, kind: EdgeKind::Return }));
+    }
+}
@@ -0,0 +1,528 @@
+//! Interactive debugger for Intcode programs.
+//!
+//! Single steps go through [`Intcode::step::<true>`], so each executed instruction is
+//! also traced to stderr and repeated states are detected. `continue` runs without
+//! tracing unless enabled with `trace on`, and stops after any watched memory access.
+//! Every instruction executed is logged so that `back` and `rewind` can undo it.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::disasm::{self, Label};
+use super::memory::Memory;
+use super::snapshot::Snapshot;
+use super::undo::DEFAULT_UNDO_LIMIT;
+use super::watch::Watch;
+use super::{ICInt, Intcode, RunResult};
+
+const HELP: &str = "\
+commands:
+  s, step [n]          execute n instructions (default 1)
+  c, continue          run until a breakpoint, or the machine stops
+  back [n]             undo the last n instructions (default 1)
+  rewind <addr>        undo instructions until the one at addr is next
+  b, break [addr]      set a breakpoint at addr, or list breakpoints
+  d, delete <addr>     remove the breakpoint at addr
+  w, watch [addr] [rw] watch addr for reads (r), writes (w) or both, or list watches
+  uw, unwatch <addr>   remove the watch on addr
+  p, print <addr> [n]  print n words of memory starting at addr
+  set <addr> <v..>     write values to memory starting at addr
+  rb [value]           show or change the relative base
+  pc [addr]            show or change the program counter
+  regs                 show the program counter, relative base and step count
+  in <v..|text>        queue input values, or a line of ASCII text
+  out                  print and drain pending output
+  dis [addr] [n]       disassemble n instructions at addr (default PC)
+  trace on|off         trace every instruction during continue
+  reset                restore the program to its original state
+  save <path>          save a snapshot of the machine (as text if path ends in .txt)
+  load <path>          restore the machine from a saved snapshot
+  h, help              show this message
+  q, quit              exit the debugger
+an empty line repeats the previous command";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Back(usize),
+    Rewind(usize),
+    Break(Option<usize>),
+    Delete(usize),
+    Watch(Option<(usize, Watch)>),
+    Unwatch(usize),
+    Print(usize, usize),
+    Set(usize, Vec<ICInt>),
+    Base(Option<ICInt>),
+    Pc(Option<usize>),
+    Regs,
+    Input(Vec<ICInt>),
+    Output,
+    Disasm(Option<usize>, usize),
+    Trace(bool),
+    Reset,
+    Save(PathBuf),
+    Load(PathBuf),
+    Help,
+    Quit,
+}
+impl FromStr for Command {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Command, String> {
+        fn num<T: FromStr>(arg: Option<&str>, what: &str) -> Result<T, String> {
+            let arg = arg.ok_or_else(|| format!("missing {}", what))?;
+            arg.parse().map_err(|_| format!("invalid {}: {:?}", what, arg))
+        }
+        fn opt<T: FromStr>(arg: Option<&str>, what: &str) -> Result<Option<T>, String> {
+            arg.map(|a| num(Some(a), what)).transpose()
+        }
+
+        let s = s.trim();
+        let (cmd, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let mut args = rest.split_whitespace();
+        let cmd = match cmd {
+            "s" | "step" => Command::Step(opt(args.next(), "count")?.unwrap_or(1)),
+            "c" | "continue" => Command::Continue,
+            "back" => Command::Back(opt(args.next(), "count")?.unwrap_or(1)),
+            "rewind" => Command::Rewind(num(args.next(), "address")?),
+            "b" | "break" => Command::Break(opt(args.next(), "address")?),
+            "d" | "delete" => Command::Delete(num(args.next(), "address")?),
+            "w" | "watch" => match opt(args.next(), "address")? {
+                None => Command::Watch(None),
+                Some(addr) => Command::Watch(Some((addr, match args.next() {
+                    None | Some("rw") => Watch::ALL,
+                    Some("r") => Watch::READ,
+                    Some("w") => Watch::WRITE,
+                    Some(other) => return Err(format!("invalid watch kind {:?}, expected r, w or rw", other)),
+                }))),
+            },
+            "uw" | "unwatch" => Command::Unwatch(num(args.next(), "address")?),
+            "p" | "print" => Command::Print(num(args.next(), "address")?, opt(args.next(), "count")?.unwrap_or(1)),
+            "set" => {
+                let addr = num(args.next(), "address")?;
+                let values = args.map(|a| num(Some(a), "value")).collect::<Result<Vec<_>, _>>()?;
+                if values.is_empty() {
+                    return Err("missing value".to_owned());
+                }
+                Command::Set(addr, values)
+            },
+            "rb" => Command::Base(opt(args.next(), "relative base")?),
+            "pc" => Command::Pc(opt(args.next(), "address")?),
+            "regs" => Command::Regs,
+            "in" => {
+                let rest = rest.trim();
+                match rest.split_whitespace().map(str::parse).collect::<Result<Vec<ICInt>, _>>() {
+                    Ok(values) if !values.is_empty() => Command::Input(values),
+                    _ if rest.is_empty() => return Err("missing input".to_owned()),
+                    _ => Command::Input(rest.bytes().chain(Some(b'\n')).map(ICInt::from).collect()),
+                }
+            },
+            "out" => Command::Output,
+            "dis" => Command::Disasm(opt(args.next(), "address")?, opt(args.next(), "count")?.unwrap_or(8)),
+            "trace" => match args.next() {
+                Some("on") => Command::Trace(true),
+                Some("off") => Command::Trace(false),
+                _ => return Err("expected `trace on` or `trace off`".to_owned()),
+            },
+            "reset" => Command::Reset,
+            "save" | "load" => {
+                let path = match rest.trim() {
+                    "" => return Err("missing path".to_owned()),
+                    path => PathBuf::from(path),
+                };
+                if cmd == "save" { Command::Save(path) } else { Command::Load(path) }
+            },
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            other => return Err(format!("unknown command {:?}, try `help`", other)),
+        };
+        Ok(cmd)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    pub cpu: Intcode,
+    breakpoints: BTreeSet<usize>,
+    trace: bool,
+}
+impl Debugger {
+    pub fn new(mut cpu: Intcode) -> Debugger {
+        cpu.record_undo(DEFAULT_UNDO_LIMIT);
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            trace: false,
+        }
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Executes up to `count` instructions, returning early if the machine stops
+    pub fn step(&mut self, count: usize) -> Option<RunResult> {
+        for _ in 0..count {
+            if let Some(rr) = self.cpu.step::<true>() {
+                return Some(rr);
+            }
+        }
+        None
+    }
+
+    /// Runs until the next breakpoint or watched access, or until the machine stops. The
+    /// instruction at the current PC is always executed, so continuing from a breakpoint works.
+    pub fn cont(&mut self) -> Option<RunResult> {
+        let accesses = self.cpu.watch_log.len();
+        loop {
+            let stop = match self.trace {
+                true => self.cpu.step::<true>(),
+                false => self.cpu.step::<false>(),
+            };
+            if stop.is_some() {
+                return stop;
+            }
+            if self.breakpoints.contains(&self.cpu.pc) || self.cpu.watch_log.len() != accesses {
+                return None;
+            }
+        }
+    }
+
+    /// Writes the disassembly of the instruction at the program counter
+    fn show_pc<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.disasm(out, self.cpu.pc, 1)
+    }
+
+    fn disasm<W: Write>(&self, out: &mut W, mut addr: usize, count: usize) -> io::Result<()> {
+        for _ in 0..count {
+            if addr >= self.cpu.ram.dense().len() {
+                break;
+            }
+            let marker = if addr == self.cpu.pc { "=>" } else if self.breakpoints.contains(&addr) { " *" } else { "  " };
+            match disasm::decode(self.cpu.ram.dense(), addr) {
+                Some(d) => {
+                    writeln!(out, "{} {:04}  {}", marker, addr, d)?;
+                    addr = d.next();
+                },
+                None => {
+                    writeln!(out, "{} {:04}  db  {}", marker, addr, self.cpu.ram[addr])?;
+                    addr += 1;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports why execution stopped, including any watched accesses since `accesses`
+    fn report<W: Write>(&self, out: &mut W, stop: Option<RunResult>, accesses: usize) -> io::Result<()> {
+        for access in &self.cpu.watch_log[accesses..] {
+            writeln!(out, "watch: {}", access)?;
+        }
+        match stop {
+            None if self.breakpoints.contains(&self.cpu.pc) => writeln!(out, "breakpoint {}", Label(self.cpu.pc))?,
+            None => {},
+            Some(RunResult::Starved) => writeln!(out, "starved: waiting for input (see `in`)")?,
+            Some(RunResult::Halted) => writeln!(out, "halted")?,
+            Some(RunResult::Fault(f)) => writeln!(out, "fault: {}", f)?,
+            Some(rr) => writeln!(out, "stopped: {:?}", rr)?,
+        }
+        self.show_pc(out)
+    }
+
+    /// Executes a single command. Returns false if the debugger should exit.
+    pub fn execute<W: Write>(&mut self, cmd: &Command, out: &mut W) -> io::Result<bool> {
+        match cmd {
+            Command::Step(n) => {
+                let accesses = self.cpu.watch_log.len();
+                let stop = self.step(*n);
+                self.report(out, stop, accesses)?;
+            },
+            Command::Continue => {
+                let accesses = self.cpu.watch_log.len();
+                let stop = self.cont();
+                self.report(out, stop, accesses)?;
+            },
+            Command::Back(n) => {
+                let undone = (0..*n).take_while(|_| self.cpu.step_back()).count();
+                if undone < *n {
+                    writeln!(out, "reached the start of history after {} steps", undone)?;
+                }
+                self.show_pc(out)?;
+            },
+            Command::Rewind(addr) => {
+                if !self.cpu.run_back_to(*addr) {
+                    writeln!(out, "{} was not executed within the recorded history", Label(*addr))?;
+                }
+                self.show_pc(out)?;
+            },
+            Command::Break(Some(addr)) => {
+                self.breakpoints.insert(*addr);
+                writeln!(out, "breakpoint set at {}", addr)?;
+            },
+            Command::Break(None) => {
+                for addr in &self.breakpoints {
+                    self.disasm(out, *addr, 1)?;
+                }
+            },
+            Command::Delete(addr) => {
+                if !self.breakpoints.remove(addr) {
+                    writeln!(out, "no breakpoint at {}", addr)?;
+                }
+            },
+            Command::Watch(Some((addr, watch))) => self.cpu.watch(*addr, *watch),
+            Command::Watch(None) => {
+                for (addr, watch) in self.cpu.watchpoints() {
+                    let kind = match (watch.read, watch.write) {
+                        (true, true) => "rw",
+                        (true, false) => "r",
+                        _ => "w",
+                    };
+                    writeln!(out, "[{}] {}", addr, kind)?;
+                }
+            },
+            Command::Unwatch(addr) => self.cpu.unwatch(*addr),
+            Command::Print(addr, count) => match addr.checked_add(*count) {
+                Some(end) => {
+                    for a in *addr..end {
+                        writeln!(out, "[{}] = {}", a, self.cpu.ram[a])?;
+                    }
+                },
+                None => writeln!(out, "{} words from {} goes past the end of memory", count, addr)?,
+            },
+            Command::Set(addr, values) => {
+                for (a, &v) in (*addr..).zip(values) {
+                    if let Err(e) = self.cpu.ram.set(a, v) {
+                        writeln!(out, "{}", e)?;
+                        break;
+                    }
+                }
+            },
+            Command::Base(Some(rb)) => self.cpu.relative_base = *rb,
+            Command::Base(None) => writeln!(out, "rb = {}", self.cpu.relative_base)?,
+            Command::Pc(Some(pc)) => {
+                self.cpu.pc = *pc;
+                self.show_pc(out)?;
+            },
+            Command::Pc(None) => self.show_pc(out)?,
+            Command::Regs => {
+                writeln!(out, "pc = {}, rb = {}, stepped = {}", self.cpu.pc, self.cpu.relative_base, self.cpu.stepped)?;
+                writeln!(out, "{} pending input, {} pending output", self.cpu.input.len(), self.cpu.output.len())?;
+            },
+            Command::Input(values) => self.cpu.input.extend(values),
+            Command::Output => {
+                let output = std::mem::take(&mut self.cpu.output);
+                let text = output.iter().all(|&c| (0..128).contains(&c) && (c as u8 == b'\n' || !(c as u8).is_ascii_control()));
+                if text && !output.is_empty() {
+                    let s: String = output.iter().map(|&c| c as u8 as char).collect();
+                    write!(out, "{}", s)?;
+                    if !s.ends_with('\n') { writeln!(out)?; }
+                } else {
+                    let nums: Vec<String> = output.iter().map(ICInt::to_string).collect();
+                    writeln!(out, "{}", nums.join(","))?;
+                }
+            },
+            Command::Disasm(addr, count) => self.disasm(out, addr.unwrap_or(self.cpu.pc), *count)?,
+            Command::Trace(on) => self.trace = *on,
+            Command::Reset => {
+                self.cpu.reset();
+                self.show_pc(out)?;
+            },
+            Command::Save(path) => match self.cpu.snapshot().save(path) {
+                Ok(()) => writeln!(out, "saved to {}", path.display())?,
+                Err(e) => writeln!(out, "unable to save {}: {}", path.display(), e)?,
+            },
+            Command::Load(path) => match Snapshot::load(path) {
+                Ok(snap) => {
+                    self.cpu.restore(&snap);
+                    self.show_pc(out)?;
+                },
+                Err(e) => writeln!(out, "unable to load {}: {}", path.display(), e)?,
+            },
+            Command::Help => writeln!(out, "{}", HELP)?,
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Reads commands from `input` until it is exhausted or `quit` is entered
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        let mut last: Option<Command> = None;
+        self.show_pc(&mut out)?;
+        write!(out, "(icdb) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let cmd = match (line.trim(), &last) {
+                ("", Some(prev)) => Ok(prev.clone()),
+                ("", None) => Err(String::new()),
+                (line, _) => line.parse::<Command>(),
+            };
+            match cmd {
+                Ok(cmd) => {
+                    if !self.execute(&cmd, &mut out)? {
+                        return Ok(());
+                    }
+                    last = Some(cmd);
+                },
+                Err(e) if e.is_empty() => {},
+                Err(e) => writeln!(out, "{}", e)?,
+            }
+            write!(out, "(icdb) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "
+            inp [n]
+    loop:   out [n]
+            add [n], #-1, [n]
+            jnz [n], #loop
+            hlt
+    n:      db 0
+    ";
+
+    fn session(dbg: &mut Debugger, script: &str) -> String {
+        let mut out = Vec::new();
+        dbg.repl(script.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!("s".parse(), Ok(Command::Step(1)));
+        assert_eq!("step 5".parse(), Ok(Command::Step(5)));
+        assert_eq!("b 12".parse(), Ok(Command::Break(Some(12))));
+        assert_eq!("p 3 2".parse(), Ok(Command::Print(3, 2)));
+        assert_eq!("set 4 1 -2".parse(), Ok(Command::Set(4, vec![1, -2])));
+        assert_eq!("in 1 2 3".parse(), Ok(Command::Input(vec![1, 2, 3])));
+        assert_eq!("in NOT A J".parse(), Ok(Command::Input("NOT A J\n".bytes().map(ICInt::from).collect())));
+        assert!("b x".parse::<Command>().is_err());
+        assert!("set 4".parse::<Command>().is_err());
+        assert_eq!("save states/a b.txt".parse(), Ok(Command::Save(PathBuf::from("states/a b.txt"))));
+        assert!("load".parse::<Command>().is_err());
+        assert_eq!("back".parse(), Ok(Command::Back(1)));
+        assert!("rewind".parse::<Command>().is_err());
+        assert!("frobnicate".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut dbg = Debugger::new(Intcode::assemble(PROGRAM).unwrap());
+        let transcript = session(&mut dbg, "c\nin 3\nb 2\nc\nout\nc\n\nout\nd 2\nc\nout\nq\n");
+        assert_eq!(transcript, concat!(
+            "=> 0000  inp [12]\n",
+            "(icdb) starved: waiting for input (see `in`)\n",
+            "=> 0000  inp [12]\n",
+            "(icdb) (icdb) breakpoint set at 2\n",
+            "(icdb) breakpoint L0002\n",
+            "=> 0002  out [12]\n",
+            "(icdb) \n",
+            "(icdb) breakpoint L0002\n",
+            "=> 0002  out [12]\n",
+            "(icdb) breakpoint L0002\n",
+            "=> 0002  out [12]\n",
+            "(icdb) 3,2\n",
+            "(icdb) (icdb) halted\n",
+            "=> 0011  hlt\n",
+            "(icdb) 1\n",
+            "(icdb) ",
+        ));
+        assert_eq!(dbg.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn memory_and_registers() {
+        let mut dbg = Debugger::new(Intcode::assemble(PROGRAM).unwrap());
+        let transcript = session(&mut dbg, "set 12 2\np 11 2\nrb 100\nrb\ns 2\nregs\npc 11\nreset\nrb\n");
+        assert_eq!(transcript, concat!(
+            "=> 0000  inp [12]\n",
+            "(icdb) (icdb) [11] = 99\n",
+            "[12] = 2\n",
+            "(icdb) (icdb) rb = 100\n",
+            "(icdb) starved: waiting for input (see `in`)\n",
+            "=> 0000  inp [12]\n",
+            "(icdb) pc = 0, rb = 100, stepped = 0\n",
+            "0 pending input, 0 pending output\n",
+            "(icdb) => 0011  hlt\n",
+            "(icdb) => 0000  inp [12]\n",
+            "(icdb) rb = 0\n",
+            "(icdb) \n",
+        ));
+    }
+
+    #[test]
+    fn huge_addresses() {
+        let mut dbg = Debugger::new(Intcode::assemble(PROGRAM).unwrap());
+        let max = usize::MAX;
+        let transcript = session(&mut dbg, &format!("p {} 2\np {} 1\np 1 {}\n", max, max - 1, max));
+        assert_eq!(transcript, format!(concat!(
+            "=> 0000  inp [12]\n",
+            "(icdb) 2 words from {max} goes past the end of memory\n",
+            "(icdb) [{max_1}] = 0\n",
+            "(icdb) {max} words from 1 goes past the end of memory\n",
+            "(icdb) \n",
+        ), max = max, max_1 = max - 1));
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut dbg = Debugger::new(Intcode::assemble(PROGRAM).unwrap());
+        let transcript = session(&mut dbg, "w 12 w\nw\nin 2\nc\nc\nuw 12\nc\n");
+        assert_eq!(transcript, concat!(
+            "=> 0000  inp [12]\n",
+            "(icdb) (icdb) [12] w\n",
+            "(icdb) (icdb) watch: PC=0000 write [12] = 2\n",
+            "=> 0002  out [12]\n",
+            "(icdb) watch: PC=0004 write [12] = 1\n",
+            "=> 0008  jnz [12], #2\n",
+            "(icdb) (icdb) halted\n",
+            "=> 0011  hlt\n",
+            "(icdb) \n",
+        ));
+    }
+
+    #[test]
+    fn time_travel() {
+        let mut dbg = Debugger::new(Intcode::assemble(PROGRAM).unwrap());
+        let transcript = session(&mut dbg, "in 3\nc\nback 3\nout\nrewind 2\np 12\nrewind 9\np 12\nback\n");
+        assert_eq!(transcript, concat!(
+            "=> 0000  inp [12]\n",
+            "(icdb) (icdb) halted\n",
+            "=> 0011  hlt\n",
+            "(icdb) => 0002  out [12]\n",
+            "(icdb) 3,2\n",
+            "(icdb) => 0002  out [12]\n",
+            "(icdb) [12] = 2\n",
+            "(icdb) L0009 was not executed within the recorded history\n",
+            "=> 0002  out [12]\n",
+            "(icdb) [12] = 2\n",
+            "(icdb) => 0008  jnz [12], #2\n",
+            "(icdb) \n",
+        ));
+    }
+
+    #[test]
+    fn disassembly() {
+        let mut dbg = Debugger::new(Intcode::assemble(PROGRAM).unwrap());
+        let transcript = session(&mut dbg, "b 8\ndis 0 6\n");
+        assert_eq!(transcript, concat!(
+            "=> 0000  inp [12]\n",
+            "(icdb) breakpoint set at 8\n",
+            "(icdb) => 0000  inp [12]\n",
+            "   0002  out [12]\n",
+            "   0004  add [12], #-1, [12]\n",
+            " * 0008  jnz [12], #2\n",
+            "   0011  hlt\n",
+            "   0012  db  0\n",
+            "(icdb) \n",
+        ));
+    }
+}
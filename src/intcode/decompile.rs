@@ -0,0 +1,578 @@
+//! Decompiler from Intcode to structured pseudo-code.
+//!
+//! Each [`Function`] recovered by the [control-flow graph](super::cfg) is lifted separately.
+//! Instructions become assignments, and jumps are turned back into `if`/`else` and loops where
+//! the shape of the code allows it, falling back to `goto` where it doesn't. Comparisons whose
+//! result is only ever used by the jump that follows them are folded into the jump's condition.
+//!
+//! Memory is named according to the call convention. Within a function, the relative base is
+//! tracked from its entry, so that each `rb+n` operand can be named by the stack slot it refers
+//! to: `ret` is the return address, `v1`, `v2`, .. are the slots of the function's frame (where
+//! its arguments arrive and its results are left) and `c1`, `c2`, .. are the slots just above
+//! the frame, which hold the arguments and results of calls made from the function. The entry
+//! function runs with the relative base starting at 0, so its stack slots are plain addresses.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Write};
+
+use super::cfg::{Block, Cfg, EdgeKind, Function};
+use super::disasm::{Decoded, Label, Op, Operand};
+use super::{ICInt, Intcode};
+
+/// A condition, as the comparison that makes it true
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cond {
+    lhs: String,
+    op: &'static str,
+    rhs: String,
+}
+impl Cond {
+    fn negate(&self) -> Cond {
+        let op = match self.op {
+            "<" => ">=",
+            ">=" => "<",
+            "==" => "!=",
+            _ => "==",
+        };
+        Cond { lhs: self.lhs.clone(), op, rhs: self.rhs.clone() }
+    }
+}
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.op, self.rhs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stmt {
+    Line(String),
+    If { cond: Cond, then: Vec<Stmt>, els: Vec<Stmt> },
+    /// Runs `body` while `pre` holds, checked before each iteration, and `post` holds, checked
+    /// after each iteration. With neither, the loop only ends by `break` or `goto`.
+    Loop { pre: Option<Cond>, body: Vec<Stmt>, post: Option<Cond> },
+    Break,
+    Continue,
+    Goto(usize),
+    /// The start of a block, which is only printed if something jumps to it with `goto`
+    Label(usize),
+}
+
+/// Where a jump goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Addr(usize),
+    /// The target is read from memory
+    Indirect(String),
+}
+
+/// How control leaves a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Exit {
+    Fall,
+    Halt,
+    Return,
+    Call { target: Target, ret: usize },
+    /// A jump, which is always taken if there's no condition
+    Jump { cond: Option<Cond>, target: Target },
+}
+
+/// Lifts a single function
+struct Lifter<'a> {
+    cfg: &'a Cfg,
+    func: &'a Function,
+    /// The relative base before each instruction, relative to its value on entry
+    deltas: HashMap<usize, ICInt>,
+    /// Comparison results that are only used by the jump right after them
+    flags: HashSet<String>,
+    /// Loop headers, with the end address of the last block that jumps back to them
+    loops: BTreeMap<usize, Vec<usize>>,
+    gotos: BTreeSet<usize>,
+    emitted: BTreeSet<usize>,
+}
+impl<'a> Lifter<'a> {
+    fn new(cfg: &'a Cfg, func: &'a Function, shared: &HashSet<ICInt>) -> Lifter<'a> {
+        let mut lifter = Lifter {
+            cfg,
+            func,
+            deltas: HashMap::new(),
+            flags: HashSet::new(),
+            loops: BTreeMap::new(),
+            gotos: BTreeSet::new(),
+            emitted: BTreeSet::new(),
+        };
+
+        // follow the relative base through the function, as far as it's adjusted by constants
+        let mut pending = vec![(func.entry, 0)];
+        let mut seen = HashSet::new();
+        while let Some((start, mut delta)) = pending.pop() {
+            if !seen.insert(start) {
+                continue;
+            }
+            for d in &cfg.blocks[&start].instructions {
+                lifter.deltas.insert(d.addr, delta);
+                if d.op == Op::Arb {
+                    match d.operands[0].imm() {
+                        Some(n) => delta += n,
+                        None => break,
+                    }
+                }
+            }
+            let succs = lifter.successors(start);
+            pending.extend(succs.into_iter().map(|s| (s, delta)));
+        }
+
+        // comparisons can be folded into a jump if nothing else uses their result
+        let mut compared = HashSet::new();
+        let mut used = HashSet::new();
+        for block in lifter.blocks() {
+            let instrs = &block.instructions;
+            for (i, d) in instrs.iter().enumerate() {
+                for (p, opnd) in d.operands.iter().enumerate() {
+                    let name = lifter.operand(d, opnd);
+                    let folded = p == 0
+                        && matches!(d.op, Op::Jnz | Op::Jez)
+                        && i > 0
+                        && matches!(instrs[i - 1].op, Op::Lt | Op::Eq)
+                        && lifter.operand(&instrs[i - 1], &instrs[i - 1].operands[2]) == name;
+                    match d.op.output_param() {
+                        Some(out) if out == p && matches!(d.op, Op::Lt | Op::Eq) => { compared.insert(name); },
+                        _ if folded => {},
+                        _ => { used.insert(name); },
+                    }
+                }
+            }
+        }
+        lifter.flags = compared.into_iter()
+            .filter(|name| !used.contains(name))
+            .filter(|name| match name.strip_prefix('[').and_then(|n| n.strip_suffix(']')) {
+                Some(addr) => addr.parse().map_or(true, |a| !shared.contains(&a)),
+                None => true,
+            })
+            .collect();
+
+        let mut loops: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for block in lifter.blocks() {
+            if let Exit::Jump { target: Target::Addr(t), .. } = lifter.exit(block) {
+                if t <= block.start() && func.blocks.contains(&t) {
+                    loops.entry(t).or_default().push(block.end());
+                }
+            }
+        }
+        lifter.loops = loops;
+        lifter
+    }
+
+    fn blocks(&self) -> impl Iterator<Item = &'a Block> + '_ {
+        self.func.blocks.iter().map(|b| &self.cfg.blocks[b])
+    }
+    /// Blocks that can follow `start` within the function
+    fn successors(&self, start: usize) -> Vec<usize> {
+        self.cfg.edges.iter()
+            .filter(|e| e.from == start && e.kind != EdgeKind::Call && self.func.blocks.contains(&e.to))
+            .map(|e| e.to)
+            .collect()
+    }
+    /// The first block of the function in `addr..stop`
+    fn next_block(&self, addr: usize, stop: usize) -> Option<&'a Block> {
+        let start = *self.func.blocks.range(addr..stop).next()?;
+        Some(&self.cfg.blocks[&start])
+    }
+
+    /// Names the memory an operand refers to, or gives its value if it's an immediate
+    fn operand(&self, d: &Decoded, opnd: &Operand) -> String {
+        match *opnd {
+            Operand::Immediate(v) => v.to_string(),
+            Operand::Position(a) => format!("[{}]", a),
+            Operand::Relative(o) => match self.deltas.get(&d.addr) {
+                Some(delta) if self.func.entry == 0 => format!("[{}]", delta + o),
+                Some(delta) => {
+                    let slot = delta + o;
+                    match self.func.frame {
+                        _ if slot == 0 => "ret".to_owned(),
+                        _ if slot < 0 => format!("up{}", -slot),
+                        Some(frame) if slot > frame => format!("c{}", slot - frame),
+                        _ => format!("v{}", slot),
+                    }
+                },
+                None => format!("rb[{:+}]", o),
+            },
+        }
+    }
+    fn args(&self, d: &Decoded) -> Vec<String> {
+        d.operands.iter().map(|o| self.operand(d, o)).collect()
+    }
+
+    /// True if `d` is a comparison that is folded into the jump that follows it
+    fn folded(&self, block: &Block, i: usize) -> bool {
+        let instrs = &block.instructions;
+        let d = &instrs[i];
+        matches!(d.op, Op::Lt | Op::Eq)
+            && i + 2 == instrs.len()
+            && matches!(instrs[i + 1].op, Op::Jnz | Op::Jez)
+            && self.flags.contains(&self.operand(d, &d.operands[2]))
+    }
+    /// True if the block ends with a call, which takes its last two instructions
+    fn is_call(&self, block: &Block) -> bool {
+        self.cfg.edges.iter().any(|e| e.from == block.start() && e.kind == EdgeKind::Return)
+    }
+
+    fn exit(&self, block: &Block) -> Exit {
+        let last = block.last();
+        let target = |d: &Decoded| match d.operands[1] {
+            Operand::Immediate(t) if t >= 0 => Target::Addr(t as usize),
+            opnd => Target::Indirect(self.operand(d, &opnd)),
+        };
+        if self.is_call(block) {
+            let ret = self.cfg.edges.iter()
+                .find(|e| e.from == block.start() && e.kind == EdgeKind::Return)
+                .unwrap().to;
+            return Exit::Call { target: target(last), ret };
+        }
+        let cond = match last.op {
+            Op::Hlt => return Exit::Halt,
+            Op::Jnz | Op::Jez => {
+                let n = block.instructions.len();
+                let taken = match last.operands[0].imm() {
+                    Some(v) if (v != 0) == (last.op == Op::Jnz) => None,
+                    Some(_) => return Exit::Fall,
+                    None if n >= 2 && self.folded(block, n - 2) => {
+                        let cmp = &block.instructions[n - 2];
+                        let [a, b, _]: [String; 3] = self.args(cmp).try_into().unwrap();
+                        Some(Cond { lhs: a, op: if cmp.op == Op::Lt { "<" } else { "==" }, rhs: b })
+                    },
+                    None => Some(Cond { lhs: self.operand(last, &last.operands[0]), op: "!=", rhs: "0".to_owned() }),
+                };
+                match last.op {
+                    Op::Jez => taken.map(|c| c.negate()),
+                    _ => taken,
+                }
+            },
+            _ => return Exit::Fall,
+        };
+        match target(last) {
+            Target::Indirect(x) if x == "ret" && cond.is_none() => Exit::Return,
+            target => Exit::Jump { cond, target },
+        }
+    }
+
+    /// Lifts every instruction of the block that isn't part of how it exits
+    fn statements(&self, block: &Block, out: &mut Vec<Stmt>) {
+        let instrs = &block.instructions;
+        let body = match (self.is_call(block), block.last().op) {
+            (true, _) => instrs.len() - 2,
+            (false, Op::Jnz | Op::Jez | Op::Hlt) => instrs.len() - 1,
+            _ => instrs.len(),
+        };
+        for (i, d) in instrs[..body].iter().enumerate() {
+            if self.folded(block, i) {
+                continue;
+            }
+            let args = self.args(d);
+            let line = match d.op {
+                Op::Add => format!("{} = {}", args[2], match (args[0].as_str(), args[1].as_str()) {
+                    ("0", x) | (x, "0") => x.to_owned(),
+                    (x, n) if n.starts_with('-') && n[1..].parse::<ICInt>().is_ok() => format!("{} - {}", x, &n[1..]),
+                    (x, y) => format!("{} + {}", x, y),
+                }),
+                Op::Mul => format!("{} = {}", args[2], match (args[0].as_str(), args[1].as_str()) {
+                    ("1", x) | (x, "1") => x.to_owned(),
+                    ("-1", x) | (x, "-1") => format!("-{}", x),
+                    (x, y) => format!("{} * {}", x, y),
+                }),
+                Op::Lt => format!("{} = {} < {}", args[2], args[0], args[1]),
+                Op::Eq => format!("{} = {} == {}", args[2], args[0], args[1]),
+                Op::Inp => format!("{} = input()", args[0]),
+                Op::Out => format!("output({})", args[0]),
+                Op::Arb if self.deltas.contains_key(&d.addr) && d.operands[0].imm().is_some() => continue,
+                Op::Arb => format!("rb += {}", args[0]),
+                Op::Jnz | Op::Jez | Op::Hlt => unreachable!("jumps and halts end a block"),
+            };
+            out.push(Stmt::Line(line));
+        }
+    }
+
+    /// The statement for an unconditional jump to `target`, if one is needed. `next` is where
+    /// control would go anyway.
+    fn jump(&mut self, target: usize, next: Option<usize>, lp: Option<(usize, usize)>) -> Option<Stmt> {
+        match lp {
+            _ if Some(target) == next => None,
+            Some((head, _)) if target == head => Some(Stmt::Continue),
+            Some((_, exit)) if target == exit => Some(Stmt::Break),
+            _ => {
+                self.gotos.insert(target);
+                Some(Stmt::Goto(target))
+            },
+        }
+    }
+
+    /// Lifts the blocks in `start..stop`, after which control goes to `follow`. `lp` is the
+    /// header and exit of the innermost loop being lifted, and `in_header` is set when lifting
+    /// the body of a loop, so that its header isn't taken for another loop.
+    fn range(&mut self, start: usize, stop: usize, follow: Option<usize>, lp: Option<(usize, usize)>, mut in_header: bool) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        let mut addr = start;
+        while let Some(block) = self.next_block(addr, stop) {
+            let b = block.start();
+            let loop_end = self.loops.get(&b).and_then(|ends| ends.iter().filter(|&&e| e <= stop).max().copied());
+            if let Some(end) = loop_end.filter(|_| !in_header) {
+                let body = self.range(b, end, Some(b), Some((b, end)), true);
+                out.push(structure_loop(body));
+                addr = end;
+                continue;
+            }
+            in_header = false;
+
+            self.emitted.insert(b);
+            out.push(Stmt::Label(b));
+            self.statements(block, &mut out);
+            // where control goes if the block doesn't jump
+            let next = match self.next_block(block.end(), stop) {
+                Some(_) => Some(block.end()),
+                None => follow,
+            };
+            addr = block.end();
+            match self.exit(block) {
+                Exit::Fall => {},
+                Exit::Halt => out.push(Stmt::Line("halt".to_owned())),
+                Exit::Return => out.push(Stmt::Line("return".to_owned())),
+                Exit::Call { target, ret } => {
+                    out.push(Stmt::Line(match target {
+                        Target::Addr(t) => format!("call f{:04}", t),
+                        Target::Indirect(x) => format!("call *{}", x),
+                    }));
+                    if ret != block.end() {
+                        out.extend(self.jump(ret, next, lp));
+                    }
+                },
+                Exit::Jump { cond: None, target: Target::Addr(t) } => out.extend(self.jump(t, next, lp)),
+                Exit::Jump { cond: None, target: Target::Indirect(x) } => out.push(Stmt::Line(format!("goto *{}", x))),
+                Exit::Jump { cond: Some(cond), target: Target::Indirect(x) } => {
+                    let then = vec![Stmt::Line(if x == "ret" { "return".to_owned() } else { format!("goto *{}", x) })];
+                    out.push(Stmt::If { cond, then, els: vec![] });
+                },
+                Exit::Jump { cond: Some(cond), target: Target::Addr(t) } => {
+                    let is_loop_edge = lp.map_or(false, |(head, exit)| t == head || t == exit);
+                    if !is_loop_edge && t > b && t <= stop {
+                        // skipping forward over a then-branch, which may itself jump over an else
+                        let then_end = self.func.blocks.range(block.end()..t).next_back()
+                            .map(|&p| &self.cfg.blocks[&p]);
+                        let els_end = then_end.and_then(|p| match self.exit(p) {
+                            Exit::Jump { cond: None, target: Target::Addr(e) } if e > t && e <= stop => Some(e),
+                            _ => None,
+                        });
+                        let after = els_end.unwrap_or(t);
+                        let then = self.range(block.end(), t, Some(after), lp, false);
+                        let els = match els_end {
+                            Some(e) => self.range(t, e, Some(e), lp, false),
+                            None => Vec::new(),
+                        };
+                        out.push(Stmt::If { cond: cond.negate(), then, els });
+                        addr = after;
+                    } else {
+                        let then = self.jump(t, None, lp).into_iter().collect();
+                        out.push(Stmt::If { cond, then, els: vec![] });
+                    }
+                },
+            }
+        }
+        out
+    }
+
+    fn lift(mut self) -> (Vec<Stmt>, BTreeSet<usize>) {
+        let end = self.blocks().map(Block::end).max().unwrap_or(0);
+        let mut body = self.range(self.func.entry, end, None, None, false);
+        // anything not reached in address order from the entry, such as code shared with
+        // another function that sits before it
+        let rest: Vec<_> = self.func.blocks.iter().copied().filter(|b| !self.emitted.contains(b)).collect();
+        for b in rest {
+            if !self.emitted.contains(&b) {
+                let block_end = self.cfg.blocks[&b].end();
+                self.gotos.insert(b);
+                body.extend(self.range(b, block_end, None, None, true));
+            }
+        }
+        (body, self.gotos)
+    }
+}
+
+/// Tidies up the body of a loop whose header is the first block of `body`
+fn structure_loop(mut body: Vec<Stmt>) -> Stmt {
+    let mut post = None;
+    if let Some(Stmt::If { then, els, .. }) = body.last() {
+        if then == &[Stmt::Continue] && els.is_empty() {
+            if let Some(Stmt::If { cond, .. }) = body.pop() {
+                post = Some(cond);
+            }
+        }
+    } else if body.last() == Some(&Stmt::Continue) {
+        body.pop();
+    }
+    let mut pre = None;
+    if post.is_none() {
+        let first = body.iter().position(|s| !matches!(s, Stmt::Label(_)));
+        if let Some(Stmt::If { cond, then, els }) = first.map(|i| &body[i]) {
+            if then == &[Stmt::Break] && els.is_empty() {
+                pre = Some(cond.negate());
+                body.remove(first.unwrap());
+            }
+        }
+    }
+    Stmt::Loop { pre, body, post }
+}
+
+fn render(f: &mut String, stmts: &[Stmt], depth: usize, gotos: &BTreeSet<usize>) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    for stmt in stmts {
+        match stmt {
+            Stmt::Line(line) => writeln!(f, "{}{}", indent, line)?,
+            Stmt::If { cond, then, els } => {
+                writeln!(f, "{}if {} {{", indent, cond)?;
+                render(f, then, depth + 1, gotos)?;
+                if !els.is_empty() {
+                    writeln!(f, "{}}} else {{", indent)?;
+                    render(f, els, depth + 1, gotos)?;
+                }
+                writeln!(f, "{}}}", indent)?;
+            },
+            Stmt::Loop { pre, body, post } => {
+                match (pre, post) {
+                    (Some(cond), _) => writeln!(f, "{}while {} {{", indent, cond)?,
+                    (None, Some(_)) => writeln!(f, "{}do {{", indent)?,
+                    (None, None) => writeln!(f, "{}loop {{", indent)?,
+                }
+                render(f, body, depth + 1, gotos)?;
+                match post {
+                    Some(cond) => writeln!(f, "{}}} while {}", indent, cond)?,
+                    None => writeln!(f, "{}}}", indent)?,
+                }
+            },
+            Stmt::Break => writeln!(f, "{}break", indent)?,
+            Stmt::Continue => writeln!(f, "{}continue", indent)?,
+            Stmt::Goto(addr) => writeln!(f, "{}goto {}", indent, Label(*addr))?,
+            Stmt::Label(addr) if gotos.contains(addr) => writeln!(f, "{}:", Label(*addr))?,
+            Stmt::Label(_) => {},
+        }
+    }
+    Ok(())
+}
+
+/// Decompiles every function of a program's control-flow graph
+pub fn decompile(cfg: &Cfg) -> String {
+    // addresses used by more than one function can't be treated as a function's scratch space
+    let mut users: HashMap<ICInt, BTreeSet<usize>> = HashMap::new();
+    for func in cfg.functions.values() {
+        for b in &func.blocks {
+            for d in &cfg.blocks[b].instructions {
+                for opnd in &d.operands {
+                    if let Operand::Position(a) = opnd {
+                        users.entry(*a).or_default().insert(func.entry);
+                    }
+                }
+            }
+        }
+    }
+    let shared = users.into_iter().filter(|(_, f)| f.len() > 1).map(|(a, _)| a).collect();
+
+    let mut out = String::new();
+    for (i, func) in cfg.functions.values().enumerate() {
+        if i != 0 {
+            out.push('\n');
+        }
+        let (body, gotos) = Lifter::new(cfg, func, &shared).lift();
+        let name = match func.entry {
+            0 => "main".to_owned(),
+            entry => format!("f{:04}", entry),
+        };
+        match func.frame {
+            Some(frame) if func.entry != 0 => writeln!(out, "fn {}() {{  // frame of {} words", name, frame).unwrap(),
+            _ => writeln!(out, "fn {}() {{", name).unwrap(),
+        }
+        render(&mut out, &body, 1, &gotos).unwrap();
+        out.push_str("}\n");
+    }
+    out
+}
+
+impl Intcode {
+    /// Decompiles the machine's current memory
+    pub fn decompile(&self) -> String {
+        decompile(&self.cfg())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structured() {
+        let ic = Intcode::assemble("
+                    arb #100
+            again:  inp [n]
+                    eq  [n], #0, [t]
+                    jnz [t], #done
+                    add [n], #0, rb+1
+                    add #back, #0, rb+0
+                    jnz #1, #sum
+            back:   out rb+1
+                    jnz #1, #again
+            done:   hlt
+
+            sum:    arb #3
+                    add #0, #0, rb-1
+            loop:   lt  rb-2, #1, [f]
+                    jnz [f], #end
+                    lt  rb-2, #10, [f]
+                    jez [f], #big
+                    add rb-1, rb-2, rb-1
+                    jnz #1, #next
+            big:    add rb-1, #10, rb-1
+            next:   add rb-2, #-1, rb-2
+                    jnz #1, #loop
+            end:    add rb-1, #0, rb-2
+                    arb #-3
+                    jez #0, rb+0
+            n:      db 0
+            t:      db 0
+            f:      db 0
+        ").unwrap();
+        assert_eq!(ic.decompile(), concat!(
+            "fn main() {\n",
+            "    loop {\n",
+            "        [75] = input()\n",
+            "        if [75] == 0 {\n",
+            "            break\n",
+            "        }\n",
+            "        [101] = [75]\n",
+            "        call f0028\n",
+            "        output([101])\n",
+            "    }\n",
+            "    halt\n",
+            "}\n",
+            "\n",
+            "fn f0028() {  // frame of 3 words\n",
+            "    v2 = 0\n",
+            "    while v1 >= 1 {\n",
+            "        if v1 < 10 {\n",
+            "            v2 = v2 + v1\n",
+            "        } else {\n",
+            "            v2 = v2 + 10\n",
+            "        }\n",
+            "        v1 = v1 - 1\n",
+            "    }\n",
+            "    v1 = v2\n",
+            "    return\n",
+            "}\n",
+        ));
+    }
+
+    #[test]
+    fn puzzle_program() {
+        let src = Intcode::parse(aoch::daystr!("13")).decompile();
+        assert!(src.starts_with("fn main() {\n"));
+        assert!(src.contains("\nfn f0578() {"));
+        assert!(src.contains("call f0578\n"));
+        assert!(src.contains("    return\n"));
+        assert!(src.contains("input()"));
+    }
+}
@@ -0,0 +1,514 @@
+//! Disassembler for Intcode programs.
+//!
+//! Operands are rendered according to their parameter mode: `[addr]` for position mode,
+//! `#imm` for immediate mode, and `rb+off` for relative mode. Immediate jump targets are
+//! given labels of the form `L0042`.
+//!
+//! Since Intcode freely mixes code and data, only words reachable from address 0 are
+//! treated as code. Reachability follows fallthrough and immediate jump targets, along
+//! with return addresses of function calls. The puzzle programs call a function by
+//! moving the return address onto the stack (`add #ret, #0, rb+0`) and then jumping
+//! unconditionally to it. Everything else is listed as `db` data.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+use smallvec::SmallVec;
+
+use super::{ICInt, Instruction, Intcode, ParamMode};
+
+/// Number of data values printed per `db` line
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Add,
+    Mul,
+    Inp,
+    Out,
+    Jnz,
+    Jez,
+    Lt,
+    Eq,
+    Arb,
+    Hlt,
+}
+impl Op {
+    pub const ALL: [Op; 10] = [Op::Add, Op::Mul, Op::Inp, Op::Out, Op::Jnz, Op::Jez, Op::Lt, Op::Eq, Op::Arb, Op::Hlt];
+
+    pub fn from_opcode(opcode: u8) -> Option<Op> {
+        Some(match opcode {
+            1 => Op::Add,
+            2 => Op::Mul,
+            3 => Op::Inp,
+            4 => Op::Out,
+            5 => Op::Jnz,
+            6 => Op::Jez,
+            7 => Op::Lt,
+            8 => Op::Eq,
+            9 => Op::Arb,
+            99 => Op::Hlt,
+            _ => return None,
+        })
+    }
+    pub fn from_mnemonic(s: &str) -> Option<Op> {
+        Op::ALL.into_iter().find(|op| op.mnemonic() == s)
+    }
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Op::Add => 1,
+            Op::Mul => 2,
+            Op::Inp => 3,
+            Op::Out => 4,
+            Op::Jnz => 5,
+            Op::Jez => 6,
+            Op::Lt => 7,
+            Op::Eq => 8,
+            Op::Arb => 9,
+            Op::Hlt => 99,
+        }
+    }
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Op::Add => "add",
+            Op::Mul => "mul",
+            Op::Inp => "inp",
+            Op::Out => "out",
+            Op::Jnz => "jnz",
+            Op::Jez => "jez",
+            Op::Lt => "lt",
+            Op::Eq => "eq",
+            Op::Arb => "arb",
+            Op::Hlt => "hlt",
+        }
+    }
+    /// Number of parameters the instruction takes
+    pub fn arity(&self) -> usize {
+        match self {
+            Op::Add | Op::Mul | Op::Lt | Op::Eq => 3,
+            Op::Jnz | Op::Jez => 2,
+            Op::Inp | Op::Out | Op::Arb => 1,
+            Op::Hlt => 0,
+        }
+    }
+    /// The index of the parameter this instruction writes to, if any
+    pub fn output_param(&self) -> Option<usize> {
+        match self {
+            Op::Add | Op::Mul | Op::Lt | Op::Eq => Some(2),
+            Op::Inp => Some(0),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.mnemonic())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+    /// `[addr]`
+    Position(ICInt),
+    /// `#imm`
+    Immediate(ICInt),
+    /// `rb+off`
+    Relative(ICInt),
+}
+impl Operand {
+    fn new(mode: ParamMode, value: ICInt) -> Operand {
+        match mode {
+            ParamMode::Position => Operand::Position(value),
+            ParamMode::Immediate => Operand::Immediate(value),
+            ParamMode::Relative => Operand::Relative(value),
+        }
+    }
+    /// The value as encoded in the program
+    pub fn raw(&self) -> ICInt {
+        match *self {
+            Operand::Position(v) | Operand::Immediate(v) | Operand::Relative(v) => v,
+        }
+    }
+    /// The parameter mode digit for this operand
+    pub fn mode_digit(&self) -> ICInt {
+        match self {
+            Operand::Position(_) => 0,
+            Operand::Immediate(_) => 1,
+            Operand::Relative(_) => 2,
+        }
+    }
+    /// Returns the constant value of this operand, if it is an immediate
+    pub fn imm(&self) -> Option<ICInt> {
+        match *self {
+            Operand::Immediate(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Operand::Position(a) => write!(f, "[{}]", a),
+            Operand::Immediate(v) => write!(f, "#{}", v),
+            Operand::Relative(o) if o < 0 => write!(f, "rb-{}", o.unsigned_abs()),
+            Operand::Relative(o) => write!(f, "rb+{}", o),
+        }
+    }
+}
+
+/// A single decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    pub addr: usize,
+    pub op: Op,
+    pub operands: SmallVec<[Operand; 3]>,
+}
+impl Decoded {
+    /// Number of words this instruction occupies in memory
+    pub fn len(&self) -> usize {
+        1 + self.operands.len()
+    }
+    /// Address of the instruction that follows this one in memory
+    pub fn next(&self) -> usize {
+        self.addr + self.len()
+    }
+    /// Returns the jump target if this is a jump with an immediate target
+    pub fn jump_target(&self) -> Option<ICInt> {
+        match self.op {
+            Op::Jnz | Op::Jez => self.operands[1].imm(),
+            _ => None,
+        }
+    }
+    /// True if execution can continue with the following instruction
+    pub fn falls_through(&self) -> bool {
+        match (self.op, self.operands.first().and_then(Operand::imm)) {
+            (Op::Hlt, _) => false,
+            (Op::Jnz, Some(c)) => c == 0,
+            (Op::Jez, Some(c)) => c != 0,
+            _ => true,
+        }
+    }
+    /// True if the jump can be taken
+    pub fn may_jump(&self) -> bool {
+        match (self.op, self.operands.first().and_then(Operand::imm)) {
+            (Op::Jnz, Some(c)) => c != 0,
+            (Op::Jez, Some(c)) => c == 0,
+            (Op::Jnz | Op::Jez, None) => true,
+            _ => false,
+        }
+    }
+    /// Returns the value if this instruction moves an immediate into memory unchanged
+    /// (`add #x, #0, ..`, `add #0, #x, ..`, `mul #x, #1, ..` or `mul #1, #x, ..`)
+    pub fn moved_immediate(&self) -> Option<ICInt> {
+        let (a, b) = (self.operands.first()?.imm()?, self.operands.get(1)?.imm()?);
+        match self.op {
+            Op::Add if b == 0 => Some(a),
+            Op::Add if a == 0 => Some(b),
+            Op::Mul if b == 1 => Some(a),
+            Op::Mul if a == 1 => Some(b),
+            _ => None,
+        }
+    }
+    /// Re-encodes this instruction into its in-memory representation
+    pub fn encode(&self) -> SmallVec<[ICInt; 4]> {
+        let mut instr = self.op.opcode() as ICInt;
+        let mut scale = 100;
+        for opnd in &self.operands {
+            instr += opnd.mode_digit() * scale;
+            scale *= 10;
+        }
+        let mut words = SmallVec::new();
+        words.push(instr);
+        words.extend(self.operands.iter().map(Operand::raw));
+        words
+    }
+    fn fmt_with_labels(&self, f: &mut fmt::Formatter<'_>, labels: &BTreeSet<usize>) -> fmt::Result {
+        write!(f, "{:<3}", self.op)?;
+        for (i, opnd) in self.operands.iter().enumerate() {
+            f.write_str(if i == 0 { " " } else { ", " })?;
+            match (self.jump_target(), i) {
+                (Some(tgt), 1) if tgt >= 0 && labels.contains(&(tgt as usize)) => write!(f, "#{}", Label(tgt as usize))?,
+                _ => write!(f, "{}", opnd)?,
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with_labels(f, &BTreeSet::new())
+    }
+}
+
+/// If `d` pushes a return address onto the stack right before an unconditional jump,
+/// returns that return address.
+pub fn call_return(ram: &[ICInt], d: &Decoded) -> Option<usize> {
+    let ret = d.moved_immediate()?;
+    if !matches!(d.operands[2], Operand::Relative(_)) || ret < 0 || ret as usize >= ram.len() {
+        return None;
+    }
+    let jump = decode(ram, d.next())?;
+    if jump.falls_through() || !jump.may_jump() {
+        return None;
+    }
+    Some(ret as usize)
+}
+
+/// Decodes the instruction at `addr`, if the word there is a valid instruction whose
+/// operands fit within `ram` and which does not write to an immediate parameter.
+pub fn decode(ram: &[ICInt], addr: usize) -> Option<Decoded> {
+    let instr = Instruction(*ram.get(addr)?);
+    let op = Op::from_opcode(instr.instr())?;
+    if instr.0 < 0 || addr + op.arity() >= ram.len() {
+        return None;
+    }
+    let mut operands = SmallVec::new();
+    for i in 0..op.arity() {
+        let mode = instr.param(i).ok()?;
+        if mode == ParamMode::Immediate && op.output_param() == Some(i) {
+            return None;
+        }
+        operands.push(Operand::new(mode, ram[addr + 1 + i]));
+    }
+    // reject stray mode digits beyond the instruction's parameters
+    if instr.0 / (10 as ICInt).pow(2 + op.arity() as u32) != 0 {
+        return None;
+    }
+    Some(Decoded { addr, op, operands })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Label(pub usize);
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "L{:04}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Code(Decoded),
+    Data { addr: usize, values: Vec<ICInt> },
+}
+impl Line {
+    pub fn addr(&self) -> usize {
+        match self {
+            Line::Code(d) => d.addr,
+            Line::Data { addr, .. } => *addr,
+        }
+    }
+}
+
+/// A disassembled program. Printing it produces the full listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    /// Addresses that are the target of an immediate jump, or are used as a return address
+    pub labels: BTreeSet<usize>,
+}
+impl Listing {
+    /// Returns the decoded instruction starting at `addr`, if it was determined to be code
+    pub fn instruction_at(&self, addr: usize) -> Option<&Decoded> {
+        let ind = self.lines.binary_search_by_key(&addr, Line::addr).ok()?;
+        match &self.lines[ind] {
+            Line::Code(d) => Some(d),
+            Line::Data { .. } => None,
+        }
+    }
+    pub fn instructions(&self) -> impl Iterator<Item = &Decoded> {
+        self.lines.iter().filter_map(|l| match l {
+            Line::Code(d) => Some(d),
+            Line::Data { .. } => None,
+        })
+    }
+}
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            if self.labels.contains(&line.addr()) {
+                writeln!(f, "{}:", Label(line.addr()))?;
+            }
+            write!(f, "  {:04}  ", line.addr())?;
+            match line {
+                Line::Code(d) => d.fmt_with_labels(f, &self.labels)?,
+                Line::Data { values, .. } => {
+                    write!(f, "db  ")?;
+                    for (i, v) in values.iter().enumerate() {
+                        if i != 0 { f.write_str(", ")?; }
+                        write!(f, "{}", v)?;
+                    }
+                },
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassembles a program, using reachability from address 0 to separate code from data.
+pub fn disassemble(ram: &[ICInt]) -> Listing {
+    let mut code: Vec<Option<Decoded>> = vec![None; ram.len()];
+    let mut covered = vec![false; ram.len()];
+    let mut labels = BTreeSet::new();
+
+    // return addresses are only tried once all else has been explored,
+    // so they cannot claim words that real control flow would disagree with.
+    let mut pending = vec![0];
+    let mut pointers: Vec<usize> = Vec::new();
+    let mut tried: HashSet<usize> = HashSet::new();
+    loop {
+        let addr = match pending.pop() {
+            Some(a) => a,
+            None => match pointers.pop() {
+                Some(p) => p,
+                None => break,
+            },
+        };
+        if addr >= ram.len() || covered[addr] || !tried.insert(addr) {
+            continue;
+        }
+        let decoded = match decode(ram, addr) {
+            Some(d) if !covered[d.addr..d.next()].iter().any(|&c| c) => d,
+            _ => continue,
+        };
+
+        if decoded.falls_through() {
+            pending.push(decoded.next());
+        }
+        if decoded.may_jump() {
+            if let Some(tgt) = decoded.jump_target().filter(|&t| t >= 0 && (t as usize) < ram.len()) {
+                labels.insert(tgt as usize);
+                pending.push(tgt as usize);
+            }
+        }
+        if let Some(ret) = call_return(ram, &decoded) {
+            pointers.push(ret);
+        }
+
+        covered[decoded.addr..decoded.next()].iter_mut().for_each(|c| *c = true);
+        code[addr] = Some(decoded);
+    }
+
+    // only keep labels for addresses that actually turned into code
+    labels.extend(code.iter().flatten().filter_map(|d| call_return(ram, d)));
+    labels.retain(|&l| code[l].is_some());
+
+    let mut lines = Vec::new();
+    let mut addr = 0;
+    while addr < ram.len() {
+        if let Some(d) = code[addr].take() {
+            addr = d.next();
+            lines.push(Line::Code(d));
+            continue;
+        }
+        let start = addr;
+        addr += 1;
+        while addr < ram.len() && !covered[addr] && addr - start < DATA_PER_LINE {
+            addr += 1;
+        }
+        lines.push(Line::Data { addr: start, values: ram[start..addr].to_vec() });
+    }
+
+    Listing { lines, labels }
+}
+
+impl Intcode {
+    /// Disassembles the machine's current memory
+    pub fn disassemble(&self) -> Listing {
+        disassemble(self.ram.dense())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operands() {
+        let d = decode(&[21002, 4, -3, 7], 0).unwrap();
+        assert_eq!(d.op, Op::Mul);
+        assert_eq!(&d.operands[..], &[Operand::Position(4), Operand::Immediate(-3), Operand::Relative(7)]);
+        assert_eq!(d.to_string(), "mul [4], #-3, rb+7");
+        assert_eq!(&d.encode()[..], &[21002, 4, -3, 7]);
+        assert_eq!(decode(&[204, -1], 0).unwrap().to_string(), "out rb-1");
+        assert_eq!(decode(&[99], 0).unwrap().to_string(), "hlt");
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode(&[1101, 1, 1], 0), None, "truncated");
+        assert_eq!(decode(&[11101, 1, 1, 0], 0), None, "immediate write");
+        assert_eq!(decode(&[301, 0, 0, 0], 0), None, "bad mode");
+        assert_eq!(decode(&[10099], 0), None, "stray mode");
+        assert_eq!(decode(&[42], 0), None, "bad opcode");
+    }
+
+    #[test]
+    fn code_and_data() {
+        // day05's equal-to-8 example: code followed by two data words
+        let listing = disassemble(&[3,9,8,9,10,9,4,9,99,-1,8]);
+        assert_eq!(listing.to_string(), concat!(
+            "  0000  inp [9]\n",
+            "  0002  eq  [9], [10], [9]\n",
+            "  0006  out [9]\n",
+            "  0008  hlt\n",
+            "  0009  db  -1, 8\n",
+        ));
+    }
+
+    #[test]
+    fn jump_labels() {
+        // day05's jump example: the jump is always taken (until self-modified), so the
+        // instruction after it is unreachable
+        let listing = disassemble(&[3,3,1105,-1,9,1101,0,0,12,4,12,99,1]);
+        assert_eq!(listing.to_string(), concat!(
+            "  0000  inp [3]\n",
+            "  0002  jnz #-1, #L0009\n",
+            "  0005  db  1101, 0, 0, 12\n",
+            "L0009:\n",
+            "  0009  out [12]\n",
+            "  0011  hlt\n",
+            "  0012  db  1\n",
+        ));
+        assert_eq!(listing.labels.iter().copied().collect::<Vec<_>>(), vec![9]);
+        assert_eq!(listing.instruction_at(2).map(|d| d.op), Some(Op::Jnz));
+        assert_eq!(listing.instruction_at(5), None);
+        assert_eq!(listing.instruction_at(12), None);
+    }
+
+    #[test]
+    fn return_addresses() {
+        // push a return address to rb+0, call a function that returns through it
+        let listing = disassemble(&[
+            109,20,          // arb #20
+            21101,10,0,0,    // add #10, #0, rb+0
+            1105,1,13,       // jnz #1, #13
+            0,               // db
+            4,20,            // out [20]
+            99,              // hlt
+            2106,0,0,        // jez #0, rb+0
+        ]);
+        assert_eq!(listing.to_string(), concat!(
+            "  0000  arb #20\n",
+            "  0002  add #10, #0, rb+0\n",
+            "  0006  jnz #1, #L0013\n",
+            "  0009  db  0\n",
+            "L0010:\n",
+            "  0010  out [20]\n",
+            "  0012  hlt\n",
+            "L0013:\n",
+            "  0013  jez #0, rb+0\n",
+        ));
+    }
+
+    #[test]
+    fn puzzle_program() {
+        let listing = Intcode::parse(aoch::daystr!("13")).disassemble();
+        // the tile drawing loop calls a function at 578, which returns to 37
+        assert!(listing.instructions().count() > 100);
+        assert!(listing.labels.contains(&578));
+        assert!(listing.labels.contains(&37));
+        assert!(!listing.labels.contains(&4));
+        assert_eq!(listing.instruction_at(0).map(|d| d.op), Some(Op::Add));
+    }
+}
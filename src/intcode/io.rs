@@ -0,0 +1,288 @@
+//! Pluggable input/output for Intcode machines.
+//!
+//! By default a machine reads from and writes to its own `input`/`output` queues. Passing
+//! an [`IntcodeIo`] to [`Intcode::run_io`] instead connects the machine directly to another
+//! source or sink - a channel to another machine, a game loop, or a terminal - without
+//! copying values between queues.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+use super::word::Word;
+use super::ICInt;
+
+/// A source of input and sink for output, for a machine computing with words of type `W`
+pub trait IntcodeIo<W = ICInt> {
+    /// Returns the next input value, or None if the machine should starve
+    fn read(&mut self) -> Option<W>;
+    fn write(&mut self, value: W);
+}
+impl<W, T: IntcodeIo<W> + ?Sized> IntcodeIo<W> for &mut T {
+    fn read(&mut self) -> Option<W> {
+        (**self).read()
+    }
+    fn write(&mut self, value: W) {
+        (**self).write(value)
+    }
+}
+
+/// A pair of queues, as used by the machine itself
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QueueIo<W = ICInt> {
+    pub input: VecDeque<W>,
+    pub output: VecDeque<W>,
+}
+impl<W: Word> QueueIo<W> {
+    pub fn new<I: IntoIterator<Item = W>>(input: I) -> QueueIo<W> {
+        QueueIo {
+            input: input.into_iter().collect(),
+            output: VecDeque::new(),
+        }
+    }
+}
+impl<W: Word> IntcodeIo<W> for QueueIo<W> {
+    fn read(&mut self) -> Option<W> {
+        self.input.pop_front()
+    }
+    fn write(&mut self, value: W) {
+        self.output.push_back(value);
+    }
+}
+
+/// Reads from and writes to channels, allowing machines on separate threads to be chained.
+#[derive(Debug)]
+pub struct ChannelIo<W = ICInt> {
+    rx: Receiver<W>,
+    tx: Sender<W>,
+    blocking: bool,
+}
+impl<W: Word> ChannelIo<W> {
+    /// Starves the machine whenever the receiver is empty
+    pub fn new(rx: Receiver<W>, tx: Sender<W>) -> ChannelIo<W> {
+        ChannelIo { rx, tx, blocking: false }
+    }
+    /// Waits for input when the receiver is empty. The machine starves once all senders hang up.
+    pub fn blocking(rx: Receiver<W>, tx: Sender<W>) -> ChannelIo<W> {
+        ChannelIo { rx, tx, blocking: true }
+    }
+}
+impl<W: Word> IntcodeIo<W> for ChannelIo<W> {
+    fn read(&mut self) -> Option<W> {
+        match self.blocking {
+            true => self.rx.recv().ok(),
+            false => self.rx.try_recv().ok(),
+        }
+    }
+    fn write(&mut self, value: W) {
+        // nobody is listening anymore, so the value can only be dropped
+        let _ = self.tx.send(value);
+    }
+}
+
+/// Calls back into closures for every value read or written
+pub struct FnIo<R, F> {
+    pub read: R,
+    pub write: F,
+}
+impl<R, F> FnIo<R, F> {
+    pub fn new<W: Word>(read: R, write: F) -> FnIo<R, F>
+        where R: FnMut() -> Option<W>, F: FnMut(W)
+    {
+        FnIo { read, write }
+    }
+}
+impl<W: Word, R: FnMut() -> Option<W>, F: FnMut(W)> IntcodeIo<W> for FnIo<R, F> {
+    fn read(&mut self) -> Option<W> {
+        (self.read)()
+    }
+    fn write(&mut self, value: W) {
+        (self.write)(value)
+    }
+}
+
+/// Feeds lines of text from a reader as ASCII codes, and writes ASCII output as text.
+/// Output values that are not ASCII are written as a number on their own line.
+pub struct AsciiLineIo<R, W> {
+    reader: R,
+    writer: W,
+    pending: VecDeque<u8>,
+}
+impl<R: BufRead, W: Write> AsciiLineIo<R, W> {
+    pub fn new(reader: R, writer: W) -> AsciiLineIo<R, W> {
+        AsciiLineIo { reader, writer, pending: VecDeque::new() }
+    }
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+impl<R: BufRead, O: Write, W: Word> IntcodeIo<W> for AsciiLineIo<R, O> {
+    fn read(&mut self) -> Option<W> {
+        if self.pending.is_empty() {
+            // the machine expects a line to be waiting for it, so show any prompt first
+            self.writer.flush().ok()?;
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            if !line.ends_with('\n') {
+                line.push('\n');
+            }
+            self.pending.extend(line.bytes().filter(|&b| b != b'\r'));
+        }
+        self.pending.pop_front().map(|b| W::from_i64(i64::from(b)))
+    }
+    fn write(&mut self, value: W) {
+        let result = match value.to_usize().and_then(|v| u8::try_from(v).ok()) {
+            Some(b) if b.is_ascii() => self.writer.write_all(&[b]),
+            _ => writeln!(self.writer, "{}", value),
+        };
+        result.expect("unable to write Intcode output");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use num::BigInt;
+    use crate::intcode::{Fault, Intcode, RunResult};
+    use crate::intcode::word::Checked;
+
+    #[test]
+    fn queues() {
+        let mut ic = Intcode::assemble("
+            loop: inp [x]
+                  mul [x], #2, [x]
+                  out [x]
+                  jnz #1, #loop
+            x: db 0
+        ").unwrap();
+        let mut io = QueueIo::new([1, 2, 3]);
+        assert_eq!(ic.run_io(&mut io), RunResult::Starved);
+        assert_eq!(io.output, vec![2, 4, 6]);
+        assert!(ic.input.is_empty() && ic.output.is_empty(), "own queues are untouched");
+    }
+
+    #[test]
+    fn other_words() {
+        // doubles each input, which overflows an i64 but not a BigInt
+        let doubler = "3,9,1002,9,2,9,4,9,99,0";
+        let big = BigInt::from(ICInt::MAX);
+        let mut ic = Intcode::<BigInt>::parse_words(doubler);
+        let mut io = QueueIo::new([big.clone()]);
+        assert_eq!(ic.run_io(&mut io), RunResult::Halted);
+        assert_eq!(io.output, vec![big * 2]);
+
+        let mut ic = Intcode::<Checked<ICInt>>::parse_words(doubler);
+        let mut io = FnIo::new(|| Some(Checked(ICInt::MAX)), |_| panic!("nothing is output"));
+        assert_eq!(ic.run_io(&mut io), RunResult::Fault(Fault::Overflow { pc: 2 }));
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(21i128).unwrap();
+        let mut ic = Intcode::<i128>::parse_words(doubler);
+        let mut io = ChannelIo::new(rx, tx);
+        assert_eq!(ic.run_io(&mut io), RunResult::Halted);
+        assert_eq!(io.rx.try_recv(), Ok(42), "sent back to itself");
+
+        // '!' doubled is 'B'
+        let mut ic = Intcode::<BigInt>::parse_words(doubler);
+        let mut io = AsciiLineIo::new("!".as_bytes(), Vec::new());
+        assert_eq!(ic.run_io(&mut io), RunResult::Halted);
+        assert_eq!(io.into_inner().1, b"B");
+    }
+
+    #[test]
+    fn closures() {
+        let mut ic = Intcode::assemble("
+            loop: inp [x]
+                  out [x]
+                  jnz [x], #loop
+                  hlt
+            x: db 0
+        ").unwrap();
+        let mut countdown = 5;
+        let mut seen = Vec::new();
+        let mut io = FnIo::new(
+            || { countdown -= 1; Some(countdown) },
+            |v| seen.push(v),
+        );
+        assert_eq!(ic.run_io(&mut io), RunResult::Halted);
+        assert_eq!(seen, vec![4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn ascii_lines() {
+        // echoes each line back in upper case, then outputs a line count after a blank line
+        let mut ic = Intcode::assemble("
+            loop: inp [c]
+                  eq  [c], #10, [t]
+                  jnz [t], #eol
+                  lt  [c], #97, [t]
+                  jnz [t], #emit
+                  add [c], #-32, [c]
+                  jez #0, #emit
+            eol:  jnz [len], #count
+                  out [lines]
+                  hlt
+            count: add [lines], #1, [lines]
+                  add #0, #-1, [len]
+            emit: out [c]
+                  add [len], #1, [len]
+                  jnz #1, #loop
+            c: db 0
+            t: db 0
+            len: db 0
+            lines: db 1000
+        ").unwrap();
+        let mut io = AsciiLineIo::new("hello\nworld\n\n".as_bytes(), Vec::new());
+        assert_eq!(ic.run_io(&mut io), RunResult::Halted);
+        let (_, written) = io.into_inner();
+        assert_eq!(String::from_utf8(written).unwrap(), "HELLO\nWORLD\n1002\n");
+
+        ic.reset();
+        let mut io = AsciiLineIo::new("abc".as_bytes(), Vec::new());
+        assert_eq!(ic.run_io(&mut io), RunResult::Starved, "starves at the end of the input");
+        assert_eq!(io.into_inner().1, b"ABC\n");
+    }
+
+    #[test]
+    fn threaded_amplifiers() {
+        // day 07's feedback loop example, with each amplifier on its own thread
+        let prog = Intcode::new(vec![
+            3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+            27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5,
+        ]);
+        let phases = [9, 8, 7, 6, 5];
+        let (senders, receivers): (Vec<_>, Vec<_>) = phases.iter().map(|_| mpsc::channel()).unzip();
+        for (tx, &phase) in senders.iter().zip(&phases) {
+            tx.send(phase).unwrap();
+        }
+        senders[0].send(0).unwrap();
+
+        let (result_tx, result_rx) = mpsc::channel();
+        let handles: Vec<_> = receivers.into_iter().enumerate()
+            .map(|(i, rx)| {
+                let tx = senders[(i + 1) % phases.len()].clone();
+                let mut ic = prog.clone();
+                let result_tx = result_tx.clone();
+                thread::spawn(move || {
+                    let mut io = ChannelIo::blocking(rx, tx);
+                    let rr = ic.run_io(&mut io);
+                    if i == 0 {
+                        // the final signal is sent to the first amplifier after it has halted
+                        result_tx.send(io.rx.recv().unwrap()).unwrap();
+                    }
+                    rr
+                })
+            })
+            .collect();
+        drop(senders);
+
+        for h in handles {
+            assert_eq!(h.join().unwrap(), RunResult::Halted);
+        }
+        assert_eq!(result_rx.recv().unwrap(), 139629729);
+    }
+}
@@ -0,0 +1,238 @@
+//! Memory backends for Intcode machines.
+//!
+//! Programs can write to any non-negative address, so memory can't simply be a `Vec` that
+//! grows to fit - one write to address 10^9 would allocate gigabytes. [`Ram`] keeps low memory
+//! (the program itself, and the stack most programs keep just after it) in a contiguous vector
+//! so the common case stays a single bounds check, and stores anything higher in fixed-size
+//! pages, up to a limit. Memory that has never been written reads as 0.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+use super::ICInt;
+use super::word::Word;
+
+/// Number of words in each page of high memory
+pub const PAGE_SIZE: usize = 1024;
+/// Addresses below this are always stored contiguously, as are all addresses of the program
+pub const DENSE_WORDS: usize = 1 << 16;
+/// Default limit on the number of pages allocated for high memory
+pub const DEFAULT_PAGE_LIMIT: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryError {
+    /// An address was negative
+    NegativeAddress(ICInt),
+    /// Writing to an address would need more memory than allowed
+    Exhausted { addr: usize },
+}
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::NegativeAddress(addr) => write!(f, "negative address ({})", addr),
+            MemoryError::Exhausted { addr } => write!(f, "memory limit exceeded writing to {}", addr),
+        }
+    }
+}
+impl std::error::Error for MemoryError {}
+
+pub trait Memory {
+    type Word: Word;
+
+    /// Reads the word at `addr`
+    fn get(&self, addr: usize) -> Self::Word;
+    /// Writes `value` to `addr`, allocating memory if needed
+    fn set(&mut self, addr: usize, value: Self::Word) -> Result<(), MemoryError>;
+    /// One past the highest address that may hold a non-zero value
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Reads from an address given as a word, as found in a program
+    fn read(&self, addr: &Self::Word) -> Result<Self::Word, MemoryError> {
+        Ok(self.get(word_addr(addr)?))
+    }
+    /// Writes to an address given as a word, as found in a program
+    fn write(&mut self, addr: &Self::Word, value: Self::Word) -> Result<(), MemoryError> {
+        self.set(word_addr(addr)?, value)
+    }
+}
+fn word_addr<W: Word>(addr: &W) -> Result<usize, MemoryError> {
+    match addr.to_usize() {
+        Some(addr) => Ok(addr),
+        None if addr.is_negative() => Err(MemoryError::NegativeAddress(addr.to_icint())),
+        // far beyond any memory limit
+        None => Err(MemoryError::Exhausted { addr: usize::MAX }),
+    }
+}
+
+/// A page of [`PAGE_SIZE`] words
+type Page<W> = Box<[W]>;
+
+/// Contiguous low memory, with paged high memory
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ram<W: Word = ICInt> {
+    dense: Vec<W>,
+    /// How far `dense` may grow before writes go to pages instead
+    dense_limit: usize,
+    /// Pages of high memory, keyed by page number
+    pages: BTreeMap<usize, Page<W>>,
+    page_limit: usize,
+    /// Returned when reading memory that has never been written
+    zero: W,
+}
+impl<W: Word> Default for Ram<W> {
+    fn default() -> Ram<W> {
+        Ram::from(Vec::new())
+    }
+}
+impl<W: Word> From<Vec<W>> for Ram<W> {
+    fn from(dense: Vec<W>) -> Ram<W> {
+        Ram {
+            dense_limit: dense.len().max(DENSE_WORDS),
+            dense,
+            pages: BTreeMap::new(),
+            page_limit: DEFAULT_PAGE_LIMIT,
+            zero: W::default(),
+        }
+    }
+}
+impl<W: Word> Ram<W> {
+    /// Limits high memory to `pages` pages of [`PAGE_SIZE`] words
+    pub fn set_page_limit(&mut self, pages: usize) {
+        self.page_limit = pages;
+    }
+    /// Low memory, which always includes the whole program
+    pub fn dense(&self) -> &[W] {
+        &self.dense
+    }
+    /// Every word of high memory that has been written, in address order
+    pub fn sparse(&self) -> impl Iterator<Item = (usize, W)> + '_ {
+        self.pages.iter().flat_map(|(&page, values)| {
+            values.iter().enumerate()
+                .filter(|(_, v)| !v.is_zero())
+                .map(move |(i, v)| (page * PAGE_SIZE + i, v.clone()))
+        })
+    }
+    /// Replaces the contents of memory with `program`, keeping the current limits
+    pub fn load(&mut self, program: &[W]) {
+        self.dense.clear();
+        self.dense.extend_from_slice(program);
+        self.dense_limit = program.len().max(DENSE_WORDS);
+        self.pages.clear();
+    }
+
+    /// Whether `addr` can be written to without going over the page limit
+    pub fn can_write(&self, addr: usize) -> bool {
+        addr < self.dense_limit
+            || self.pages.contains_key(&(addr / PAGE_SIZE))
+            || self.pages.len() < self.page_limit
+    }
+
+    /// Finds the word for `addr`, allocating it if necessary
+    fn word_mut(&mut self, addr: usize) -> Result<&mut W, MemoryError> {
+        if addr < self.dense.len() {
+            return Ok(&mut self.dense[addr]);
+        }
+        if addr < self.dense_limit {
+            self.dense.resize(addr + 1, W::default());
+            return Ok(&mut self.dense[addr]);
+        }
+        let pages = self.pages.len();
+        let page = match self.pages.entry(addr / PAGE_SIZE) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(_) if pages >= self.page_limit => {
+                return Err(MemoryError::Exhausted { addr });
+            },
+            Entry::Vacant(e) => e.insert(vec![W::default(); PAGE_SIZE].into_boxed_slice()),
+        };
+        Ok(&mut page[addr % PAGE_SIZE])
+    }
+}
+impl<W: Word> Memory for Ram<W> {
+    type Word = W;
+
+    #[inline]
+    fn get(&self, addr: usize) -> W {
+        self[addr].clone()
+    }
+    #[inline]
+    fn set(&mut self, addr: usize, value: W) -> Result<(), MemoryError> {
+        if let Some(word) = self.dense.get_mut(addr) {
+            *word = value;
+            return Ok(());
+        }
+        if value.is_zero() && addr >= self.dense_limit && !self.pages.contains_key(&(addr / PAGE_SIZE)) {
+            // already reads as 0, so there's no need for a page
+            return Ok(());
+        }
+        *self.word_mut(addr)? = value;
+        Ok(())
+    }
+    fn len(&self) -> usize {
+        match self.pages.keys().next_back() {
+            Some(page) => (page + 1) * PAGE_SIZE,
+            None => self.dense.len(),
+        }
+    }
+}
+impl<W: Word> Index<usize> for Ram<W> {
+    type Output = W;
+    #[inline]
+    fn index(&self, addr: usize) -> &W {
+        if let Some(word) = self.dense.get(addr) {
+            return word;
+        }
+        match self.pages.get(&(addr / PAGE_SIZE)) {
+            Some(page) => &page[addr % PAGE_SIZE],
+            None => &self.zero,
+        }
+    }
+}
+/// Allocates memory as needed, panicking if the limit is exceeded
+impl<W: Word> IndexMut<usize> for Ram<W> {
+    fn index_mut(&mut self, addr: usize) -> &mut W {
+        self.word_mut(addr).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dense_and_sparse() {
+        let mut ram = Ram::<ICInt>::from(vec![1, 2, 3]);
+        assert_eq!((ram[1], ram[3], ram[1 << 40]), (2, 0, 0));
+        ram[5] = 7;
+        assert_eq!(ram.dense(), &[1, 2, 3, 0, 0, 7]);
+
+        ram.set(1_000_000_000, 9).unwrap();
+        ram.set(1_000_000_001, 0).unwrap();
+        ram.set(2_000_000_000, 0).unwrap();
+        assert_eq!(ram.get(1_000_000_000), 9);
+        assert_eq!(ram.sparse().collect::<Vec<_>>(), vec![(1_000_000_000, 9)]);
+        assert_eq!(ram.len(), (1_000_000_000 / PAGE_SIZE + 1) * PAGE_SIZE);
+        assert_eq!(ram.dense().len(), 6, "high writes don't grow low memory");
+
+        ram.load(&[99]);
+        assert_eq!((ram.len(), ram[1_000_000_000]), (1, 0));
+    }
+
+    #[test]
+    fn limits() {
+        let mut ram = Ram::<ICInt>::default();
+        ram.set_page_limit(2);
+        ram.set(DENSE_WORDS, 1).unwrap();
+        ram.set(DENSE_WORDS + PAGE_SIZE - 1, 2).unwrap();
+        ram.set(5 * DENSE_WORDS, 3).unwrap();
+        assert_eq!(ram.set(6 * DENSE_WORDS, 4), Err(MemoryError::Exhausted { addr: 6 * DENSE_WORDS }));
+        assert_eq!(ram.set(6 * DENSE_WORDS, 0), Ok(()));
+        assert_eq!(ram.write(&-3, 1), Err(MemoryError::NegativeAddress(-3)));
+        assert_eq!(ram.read(&-1), Err(MemoryError::NegativeAddress(-1)));
+        assert_eq!(ram.read(&(5 * DENSE_WORDS as ICInt)), Ok(3));
+    }
+}
@@ -0,0 +1,232 @@
+//! Execution profiling and coverage.
+//!
+//! While profiling is enabled with [`Intcode::start_profile`], every instruction executed -
+//! including the final `hlt` - is counted by address and by opcode, along with every backward
+//! jump taken. Profiles are kept across [`Intcode::reset`], so the runs of a program that is
+//! restarted for each query (such as day 19's beam scanner) add up to a single profile.
+//!
+//! A [`ProfileReport`] combines a profile with the program it was made from to find the
+//! hottest instructions and loops, and the parts of the program that never ran.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::disasm::{self, Label, Op};
+use super::word::Word;
+use super::{ICInt, Intcode};
+
+/// Number of instructions and loops listed in a report
+const HOTTEST: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Profile {
+    /// Times the instruction at each address was executed
+    pub hits: BTreeMap<usize, usize>,
+    /// Instructions executed, by opcode
+    pub ops: BTreeMap<u8, usize>,
+    /// Times each backward jump was taken, keyed by the addresses of the jump and its target
+    pub back_jumps: BTreeMap<(usize, usize), usize>,
+    /// Whether the machine is stopped at a `hlt` that has already been counted, which running
+    /// it again only executes again
+    halted: bool,
+}
+impl Profile {
+    /// Counts an instruction at `pc`, after which execution continued at `next`, if the
+    /// machine didn't halt
+    pub(super) fn record(&mut self, pc: usize, op: u8, next: Option<usize>) {
+        if next.is_none() && std::mem::replace(&mut self.halted, true) {
+            return;
+        }
+        self.halted = next.is_none();
+        *self.hits.entry(pc).or_default() += 1;
+        *self.ops.entry(op).or_default() += 1;
+        if let Some(next) = next.filter(|&next| next <= pc) {
+            *self.back_jumps.entry((pc, next)).or_default() += 1;
+        }
+    }
+    /// Called when the machine is reset, so that halting again counts
+    pub(super) fn restart(&mut self) {
+        self.halted = false;
+    }
+    /// Total number of instructions executed
+    pub fn steps(&self) -> usize {
+        self.hits.values().sum()
+    }
+    pub fn report(&self, program: &[ICInt]) -> ProfileReport {
+        let mut hottest: Vec<_> = self.hits.iter().map(|(&addr, &hits)| (addr, hits)).collect();
+        hottest.sort_by_key(|&(addr, hits)| (std::cmp::Reverse(hits), addr));
+        hottest.truncate(HOTTEST);
+
+        // a loop runs from the target of a backward jump to the jump itself
+        let mut loops: Vec<_> = self.back_jumps.iter()
+            .map(|(&(end, start), &iterations)| HotLoop {
+                start,
+                end,
+                iterations,
+                steps: self.hits.range(start..=end).map(|(_, &h)| h).sum(),
+            })
+            .collect();
+        loops.sort_by_key(|l| (std::cmp::Reverse(l.steps), l.start));
+        loops.truncate(HOTTEST);
+
+        let mut covered = vec![false; program.len()];
+        for &addr in self.hits.keys().filter(|&&a| a < program.len()) {
+            let len = disasm::decode(program, addr).map_or(1, |d| d.len());
+            covered[addr..(addr + len).min(program.len())].iter_mut().for_each(|c| *c = true);
+        }
+        let mut unexecuted = Vec::new();
+        let mut addr = 0;
+        while addr < covered.len() {
+            if covered[addr] {
+                addr += 1;
+                continue;
+            }
+            let start = addr;
+            while addr < covered.len() && !covered[addr] {
+                addr += 1;
+            }
+            unexecuted.push((start, addr));
+        }
+
+        ProfileReport {
+            steps: self.steps(),
+            ops: self.ops.iter().map(|(&op, &n)| (op, n)).collect(),
+            hottest: hottest.into_iter()
+                .map(|(addr, hits)| (addr, hits, disasm::decode(program, addr).map(|d| d.to_string())))
+                .collect(),
+            loops,
+            program_len: program.len(),
+            covered: covered.iter().filter(|&&c| c).count(),
+            unexecuted,
+        }
+    }
+}
+
+/// A loop found from a backward jump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotLoop {
+    /// The target of the backward jump
+    pub start: usize,
+    /// The address of the backward jump
+    pub end: usize,
+    /// Times the jump was taken
+    pub iterations: usize,
+    /// Instructions executed between `start` and `end`, whether or not as part of the loop
+    pub steps: usize,
+}
+
+/// A summary of a [`Profile`]. Printing it gives a human readable report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileReport {
+    pub steps: usize,
+    /// Instructions executed by opcode
+    pub ops: Vec<(u8, usize)>,
+    /// The most executed instructions, with their hit counts and disassembly
+    pub hottest: Vec<(usize, usize, Option<String>)>,
+    /// The loops that executed the most instructions
+    pub loops: Vec<HotLoop>,
+    pub program_len: usize,
+    /// Number of words of the program that were executed, as an instruction or its parameters
+    pub covered: usize,
+    /// Half-open ranges of addresses that were never executed
+    pub unexecuted: Vec<(usize, usize)>,
+}
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |n: usize, of: usize| 100.0 * n as f64 / of.max(1) as f64;
+        writeln!(f, "{} instructions executed, covering {} of {} words ({:.1}%)",
+            self.steps, self.covered, self.program_len, percent(self.covered, self.program_len))?;
+
+        writeln!(f, "\nby opcode:")?;
+        for &(op, n) in &self.ops {
+            let name = Op::from_opcode(op).map_or("???", |op| op.mnemonic());
+            writeln!(f, "  {:<4} {:>12}  {:>5.1}%", name, n, percent(n, self.steps))?;
+        }
+
+        writeln!(f, "\nhottest instructions:")?;
+        for (addr, hits, dis) in &self.hottest {
+            writeln!(f, "  {:>12}  {:04}  {}", hits, addr, dis.as_deref().unwrap_or("??"))?;
+        }
+
+        writeln!(f, "\nhottest loops:")?;
+        if self.loops.is_empty() {
+            writeln!(f, "  none")?;
+        }
+        for l in &self.loops {
+            writeln!(f, "  {}..{}  {:>12} iterations  {:>12} steps", Label(l.start), Label(l.end), l.iterations, l.steps)?;
+        }
+
+        writeln!(f, "\nnever executed:")?;
+        if self.unexecuted.is_empty() {
+            writeln!(f, "  none")?;
+        }
+        for &(start, end) in &self.unexecuted {
+            match end - start {
+                1 => writeln!(f, "  {:04}", start)?,
+                n => writeln!(f, "  {:04}..{:04}  ({} words)", start, end - 1, n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Word> Intcode<W> {
+    /// Starts counting executed instructions, discarding any previous profile
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+    /// Stops profiling, returning the counts made since profiling started
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+}
+impl Intcode {
+    /// Reports on the profile so far against the original program, if profiling
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        Some(self.profile.as_ref()?.report(&self.original))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::RunResult;
+
+    #[test]
+    fn countdown() {
+        let mut ic = Intcode::assemble("
+                    inp [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jnz [n], #loop
+                    hlt
+            unused: out #1
+                    hlt
+            n:      db 0
+        ").unwrap();
+        ic.start_profile();
+        for n in [3, 2] {
+            ic.reset();
+            ic.input.push_back(n);
+            assert_eq!(ic.run(), RunResult::Halted);
+            assert_eq!(ic.run(), RunResult::Halted, "already halted");
+        }
+
+        let profile = ic.profile().unwrap();
+        assert_eq!(profile.steps(), 2 * 2 + 5 * 3, "including each hlt");
+        assert_eq!(profile.hits[&2], 5);
+        assert_eq!(profile.ops[&4], 5);
+        assert_eq!(profile.back_jumps, BTreeMap::from([((8, 2), 3)]));
+
+        let report = ic.profile_report().unwrap();
+        assert_eq!(report.hottest[0], (2, 5, Some("out [15]".to_owned())));
+        assert_eq!(report.loops, vec![HotLoop { start: 2, end: 8, iterations: 3, steps: 15 }]);
+        assert_eq!(report.unexecuted, vec![(12, 16)]);
+        let text = report.to_string();
+        assert!(text.starts_with("19 instructions executed, covering 12 of 16 words (75.0%)\n"), "{}", text);
+        assert!(text.contains("\n  0012..0015  (4 words)\n"), "{}", text);
+    }
+}
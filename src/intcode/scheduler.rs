@@ -0,0 +1,272 @@
+//! Cooperative scheduling of several Intcode machines whose outputs feed each other's inputs.
+//!
+//! Each round, every machine that has not halted is run until it stops, and then its output
+//! is delivered according to its [`Route`]. Rounds repeat until every machine has halted,
+//! one of them stops unexpectedly, or no machine is able to make progress.
+
+use std::collections::VecDeque;
+
+use super::{ICInt, Intcode, RunResult};
+
+/// Where a machine's output is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    /// Every value is appended to the input of another machine
+    Machine(usize),
+    /// Values are grouped into packets of this many values. The first value of a packet is
+    /// the index of the machine that receives the rest. Packets addressed to anything other
+    /// than a machine are collected in [`Scheduler::outbound`].
+    Packets(usize),
+    /// Values are left in the machine's own output queue
+    Hold,
+}
+
+/// A packet addressed to something other than one of the scheduled machines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    /// Index of the machine that sent the packet
+    pub src: usize,
+    pub dest: ICInt,
+    pub data: Vec<ICInt>,
+}
+
+/// Why the scheduler stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use]
+pub enum Outcome {
+    /// Every machine has halted
+    Halted,
+    /// No machine produced output or had input waiting for a whole round
+    Deadlock,
+    /// A machine stopped for a reason other than halting or needing input
+    Fault { machine: usize, result: RunResult },
+    /// Packets were sent outside the network, and are waiting in [`Scheduler::outbound`]
+    Outbound,
+}
+
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    pub machines: Vec<Intcode>,
+    routes: Vec<Route>,
+    /// Fed to a machine whenever it begins its turn with no input waiting
+    idle_input: Option<ICInt>,
+    pub outbound: VecDeque<Packet>,
+    /// Every packet sent, if enabled with [`Scheduler::log_packets`]
+    pub packet_log: Vec<Packet>,
+    log_packets: bool,
+    rounds: usize,
+}
+impl Scheduler {
+    /// Creates a scheduler where every machine holds on to its output until routed elsewhere
+    pub fn new(machines: Vec<Intcode>) -> Scheduler {
+        Scheduler {
+            routes: vec![Route::Hold; machines.len()],
+            machines,
+            idle_input: None,
+            outbound: VecDeque::new(),
+            packet_log: Vec::new(),
+            log_packets: false,
+            rounds: 0,
+        }
+    }
+    /// Creates a scheduler where each machine's output is fed into the next, and the last
+    /// machine's output is fed back into the first
+    pub fn ring(machines: Vec<Intcode>) -> Scheduler {
+        let n = machines.len();
+        let mut sched = Scheduler::new(machines);
+        for i in 0..n {
+            sched.routes[i] = Route::Machine((i + 1) % n);
+        }
+        sched
+    }
+    pub fn route(&mut self, machine: usize, route: Route) {
+        if let Route::Machine(dest) = route {
+            assert!(dest < self.machines.len(), "route to unknown machine {}", dest);
+        }
+        if let Route::Packets(len) = route {
+            assert!(len > 0, "packets must contain at least an address");
+        }
+        self.routes[machine] = route;
+    }
+    /// Supplies `value` to machines that would otherwise starve, such as day 23's -1
+    pub fn idle_input(&mut self, value: ICInt) {
+        self.idle_input = Some(value);
+    }
+    /// Records every packet sent, whether delivered within the network or not
+    pub fn log_packets(&mut self) {
+        self.log_packets = true;
+    }
+    /// Number of rounds run so far
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    /// Runs every machine once, returning an outcome if the scheduler should stop.
+    pub fn round(&mut self) -> Option<Outcome> {
+        self.rounds += 1;
+        let mut progress = false;
+        for i in 0..self.machines.len() {
+            let cpu = &mut self.machines[i];
+            if cpu.is_halted() {
+                continue;
+            }
+            if !cpu.input.is_empty() {
+                progress = true;
+            } else if let Some(v) = self.idle_input {
+                cpu.input.push_back(v);
+            }
+
+            let held = cpu.output.len();
+            match cpu.run() {
+                RunResult::Halted | RunResult::Starved => {},
+                result => return Some(Outcome::Fault { machine: i, result }),
+            }
+            if cpu.output.len() > held {
+                progress = true;
+            }
+            self.deliver(i);
+        }
+
+        if self.machines.iter().all(Intcode::is_halted) {
+            Some(Outcome::Halted)
+        } else if !self.outbound.is_empty() {
+            Some(Outcome::Outbound)
+        } else if !progress {
+            Some(Outcome::Deadlock)
+        } else {
+            None
+        }
+    }
+    /// Runs rounds until every machine halts, a machine faults, packets leave the network,
+    /// or the machines deadlock.
+    pub fn run(&mut self) -> Outcome {
+        loop {
+            if let Some(outcome) = self.round() {
+                return outcome;
+            }
+        }
+    }
+
+    /// Moves the output of machine `src` along its route
+    fn deliver(&mut self, src: usize) {
+        match self.routes[src] {
+            Route::Hold => {},
+            Route::Machine(dest) => {
+                let values = std::mem::take(&mut self.machines[src].output);
+                self.machines[dest].input.extend(values);
+            },
+            Route::Packets(len) => {
+                // incomplete packets wait for the rest of their values
+                while self.machines[src].output.len() >= len {
+                    let mut data: Vec<_> = self.machines[src].output.drain(..len).collect();
+                    let dest = data.remove(0);
+                    let packet = Packet { src, dest, data };
+                    if self.log_packets {
+                        self.packet_log.push(packet.clone());
+                    }
+                    match usize::try_from(dest) {
+                        Ok(d) if d < self.machines.len() => self.machines[d].input.extend(packet.data),
+                        _ => self.outbound.push_back(packet),
+                    }
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Fault;
+
+    #[test]
+    fn feedback_ring() {
+        // day 07's feedback loop example
+        let prog = Intcode::new(vec![
+            3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+            27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5,
+        ]);
+        let machines = [9, 8, 7, 6, 5].iter()
+            .map(|&phase| {
+                let mut ic = prog.clone();
+                ic.input.push_back(phase);
+                ic
+            })
+            .collect();
+        let mut sched = Scheduler::ring(machines);
+        sched.machines[0].input.push_back(0);
+        assert_eq!(sched.run(), Outcome::Halted);
+        assert_eq!(sched.rounds(), 5);
+        assert_eq!(sched.machines[0].input, vec![139629729]);
+    }
+
+    #[test]
+    fn deadlock_and_fault() {
+        let echo = Intcode::assemble("
+            loop: inp [x]
+                  out [x]
+                  jnz #1, #loop
+            x: db 0
+        ").unwrap();
+        let mut sched = Scheduler::ring(vec![echo.clone(), echo.clone()]);
+        assert_eq!(sched.run(), Outcome::Deadlock);
+
+        // a value passed around the ring keeps it busy, until one of the machines is broken
+        sched.machines[1].input.push_back(3);
+        assert_eq!(sched.round(), None);
+        assert_eq!(sched.round(), None);
+        sched.machines[0].ram[0] = 1105;
+        sched.machines[0].ram[1] = 1;
+        sched.machines[0].ram[2] = -7;
+        assert_eq!(sched.run(), Outcome::Fault {
+            machine: 0,
+            result: RunResult::Fault(Fault::NegativeJump { pc: 0, target: -7 }),
+        });
+    }
+
+    #[test]
+    fn packets() {
+        // reads its own address, then forwards every (x, y) it receives to the next address.
+        // The node at address 0 starts things off.
+        let node = Intcode::assemble("
+                  inp [addr]
+                  add [addr], #1, [next]
+                  jnz [addr], #loop
+                  out #1
+                  out #7
+                  out #5
+            loop: inp [x]
+                  eq  [x], #-1, [t]
+                  jnz [t], #loop
+                  inp [y]
+                  out [next]
+                  out [x]
+                  out [y]
+                  jnz #1, #loop
+            addr: db 0
+            x: db 0
+            y: db 0
+            t: db 0
+            next: db 0
+        ").unwrap();
+        let machines = (0..3)
+            .map(|i| {
+                let mut ic = node.clone();
+                ic.input.push_back(i);
+                ic
+            })
+            .collect();
+        let mut sched = Scheduler::new(machines);
+        for i in 0..3 {
+            sched.route(i, Route::Packets(3));
+        }
+        sched.idle_input(-1);
+        sched.log_packets();
+        assert_eq!(sched.run(), Outcome::Outbound);
+        assert_eq!(sched.packet_log.iter().map(|p| (p.src, p.dest)).collect::<Vec<_>>(), vec![(0, 1), (1, 2), (2, 3)]);
+        // 0 -> 1 -> 2 -> 3, which is outside of the network
+        assert_eq!(sched.outbound.pop_front(), Some(Packet { src: 2, dest: 3, data: vec![7, 5] }));
+        assert!(sched.outbound.is_empty());
+        assert_eq!(sched.run(), Outcome::Deadlock);
+    }
+}
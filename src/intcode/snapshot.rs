@@ -0,0 +1,391 @@
+//! Saving and restoring the complete state of an Intcode machine.
+//!
+//! A [`Snapshot`] captures everything needed to resume execution later - registers, memory,
+//! the original program and any pending input and output - but not debugging state such as
+//! watchpoints. Snapshots can be kept in memory to branch from, or written to disk in either
+//! a compact binary format or a text format meant for reading and editing by hand. High
+//! memory is stored as a list of `address=value` pairs, leaving out anything that reads as 0:
+//!
+//! ```text
+//! intcode snapshot
+//! pc 2
+//! rb 0
+//! stepped 1
+//! ram 3,0,4,0,99
+//! sparse
+//! original 3,0,4,0,99
+//! input
+//! output 7
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::memory::{Memory, Ram};
+use super::{ICInt, Intcode};
+
+const MAGIC: &[u8] = b"ICS\x01";
+const HEADER: &str = "intcode snapshot";
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Snapshot {
+    pub pc: usize,
+    pub relative_base: ICInt,
+    pub stepped: usize,
+    pub ram: Ram,
+    pub original: Vec<ICInt>,
+    pub input: VecDeque<ICInt>,
+    pub output: VecDeque<ICInt>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The data does not start with a snapshot header
+    BadHeader,
+    /// The binary data ended in the middle of a value
+    Truncated,
+    /// A value was too large for its field
+    Overflow,
+    /// There was more binary data after the snapshot
+    TrailingBytes,
+    /// A line of the text format could not be understood
+    BadLine(usize),
+    MissingField(&'static str),
+}
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadHeader => write!(f, "not an Intcode snapshot"),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Overflow => write!(f, "value out of range in snapshot"),
+            SnapshotError::TrailingBytes => write!(f, "unexpected data after snapshot"),
+            SnapshotError::BadLine(line) => write!(f, "invalid snapshot line {}", line),
+            SnapshotError::MissingField(field) => write!(f, "snapshot is missing `{}`", field),
+        }
+    }
+}
+impl std::error::Error for SnapshotError {}
+
+/// Appends `v` as a zigzag-encoded LEB128 varint, so that small values of either sign are short
+fn put_int(buf: &mut Vec<u8>, v: ICInt) {
+    let mut z = ((v << 1) ^ (v >> (ICInt::BITS - 1))) as u128;
+    loop {
+        let byte = (z & 0x7f) as u8;
+        z >>= 7;
+        if z == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+fn put_seq<'a, I: ExactSizeIterator<Item = &'a ICInt>>(buf: &mut Vec<u8>, values: I) {
+    put_int(buf, values.len() as ICInt);
+    for &v in values {
+        put_int(buf, v);
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+}
+impl Reader<'_> {
+    fn int(&mut self) -> Result<ICInt, SnapshotError> {
+        let mut z: u128 = 0;
+        let mut shift = 0;
+        loop {
+            let (&byte, rest) = self.bytes.split_first().ok_or(SnapshotError::Truncated)?;
+            self.bytes = rest;
+            let part = (byte & 0x7f) as u128;
+            if shift > 126 || (shift == 126 && part > 0b11) {
+                return Err(SnapshotError::Overflow);
+            }
+            z |= part << shift;
+            if byte & 0x80 == 0 {
+                return Ok((z >> 1) as ICInt ^ -((z & 1) as ICInt));
+            }
+            shift += 7;
+        }
+    }
+    fn usize(&mut self) -> Result<usize, SnapshotError> {
+        usize::try_from(self.int()?).map_err(|_| SnapshotError::Overflow)
+    }
+    fn seq<C: FromIterator<ICInt>>(&mut self) -> Result<C, SnapshotError> {
+        let len = self.usize()?;
+        (0..len).map(|_| self.int()).collect()
+    }
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        put_int(&mut buf, self.pc as ICInt);
+        put_int(&mut buf, self.relative_base);
+        put_int(&mut buf, self.stepped as ICInt);
+        put_seq(&mut buf, self.ram.dense().iter());
+        let sparse: Vec<_> = self.ram.sparse().collect();
+        put_int(&mut buf, sparse.len() as ICInt);
+        for (addr, v) in sparse {
+            put_int(&mut buf, addr as ICInt);
+            put_int(&mut buf, v);
+        }
+        put_seq(&mut buf, self.original.iter());
+        put_seq(&mut buf, self.input.iter());
+        put_seq(&mut buf, self.output.iter());
+        buf
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let bytes = bytes.strip_prefix(MAGIC).ok_or(SnapshotError::BadHeader)?;
+        let mut r = Reader { bytes };
+        let snap = Snapshot {
+            pc: r.usize()?,
+            relative_base: r.int()?,
+            stepped: r.usize()?,
+            ram: {
+                let mut ram = Ram::from(r.seq::<Vec<_>>()?);
+                for _ in 0..r.usize()? {
+                    let addr = r.usize()?;
+                    ram.set(addr, r.int()?).map_err(|_| SnapshotError::Overflow)?;
+                }
+                ram
+            },
+            original: r.seq()?,
+            input: r.seq()?,
+            output: r.seq()?,
+        };
+        if !r.bytes.is_empty() {
+            return Err(SnapshotError::TrailingBytes);
+        }
+        Ok(snap)
+    }
+
+    /// Writes the snapshot to `path`, in the text format if it has a `.txt` extension
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        match path.extension() {
+            Some(ext) if ext == "txt" => fs::write(path, self.to_string()),
+            _ => fs::write(path, self.to_bytes()),
+        }
+    }
+    /// Reads a snapshot from `path`, in either format
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+        let bytes = fs::read(path)?;
+        let snap = match bytes.starts_with(MAGIC) {
+            true => Snapshot::from_bytes(&bytes),
+            false => std::str::from_utf8(&bytes)
+                .map_err(|_| SnapshotError::BadHeader)
+                .and_then(str::parse),
+        };
+        snap.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn seq<'a, I: Iterator<Item = &'a ICInt>>(f: &mut fmt::Formatter<'_>, name: &str, values: I) -> fmt::Result {
+            write!(f, "{}", name)?;
+            for (i, v) in values.enumerate() {
+                write!(f, "{}{}", if i == 0 { ' ' } else { ',' }, v)?;
+            }
+            writeln!(f)
+        }
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "pc {}", self.pc)?;
+        writeln!(f, "rb {}", self.relative_base)?;
+        writeln!(f, "stepped {}", self.stepped)?;
+        seq(f, "ram", self.ram.dense().iter())?;
+        write!(f, "sparse")?;
+        for (i, (addr, v)) in self.ram.sparse().enumerate() {
+            write!(f, "{}{}={}", if i == 0 { ' ' } else { ',' }, addr, v)?;
+        }
+        writeln!(f)?;
+        seq(f, "original", self.original.iter())?;
+        seq(f, "input", self.input.iter())?;
+        seq(f, "output", self.output.iter())
+    }
+}
+impl FromStr for Snapshot {
+    type Err = SnapshotError;
+    fn from_str(s: &str) -> Result<Snapshot, SnapshotError> {
+        fn seq<C: FromIterator<ICInt>>(values: &str) -> Option<C> {
+            values.split(',')
+                .filter_map(aoch::parsing::trimmed)
+                .map(|v| v.parse().ok())
+                .collect()
+        }
+
+        let mut lines = s.lines().map(str::trim).enumerate().filter(|(_, l)| !l.is_empty());
+        if lines.next().map(|(_, l)| l) != Some(HEADER) {
+            return Err(SnapshotError::BadHeader);
+        }
+        let (mut pc, mut rb, mut stepped) = (None, None, None);
+        let (mut ram, mut original, mut input, mut output) = (None, None, None, None);
+        for (i, line) in lines {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let ok = match key {
+                "pc" => { pc = value.parse().ok(); pc.is_some() },
+                "rb" => { rb = value.parse().ok(); rb.is_some() },
+                "stepped" => { stepped = value.parse().ok(); stepped.is_some() },
+                "ram" => { ram = seq::<Vec<_>>(value).map(Ram::from); ram.is_some() },
+                "sparse" => {
+                    let ram = ram.as_mut().ok_or(SnapshotError::BadLine(i + 1))?;
+                    value.split(',')
+                        .filter_map(aoch::parsing::trimmed)
+                        .all(|pair| match pair.split_once('=') {
+                            Some((a, v)) => match (a.parse(), v.parse()) {
+                                (Ok(a), Ok(v)) => ram.set(a, v).is_ok(),
+                                _ => false,
+                            },
+                            None => false,
+                        })
+                },
+                "original" => { original = seq(value); original.is_some() },
+                "input" => { input = seq(value); input.is_some() },
+                "output" => { output = seq(value); output.is_some() },
+                _ => false,
+            };
+            if !ok {
+                return Err(SnapshotError::BadLine(i + 1));
+            }
+        }
+
+        Ok(Snapshot {
+            pc: pc.ok_or(SnapshotError::MissingField("pc"))?,
+            relative_base: rb.ok_or(SnapshotError::MissingField("rb"))?,
+            stepped: stepped.ok_or(SnapshotError::MissingField("stepped"))?,
+            ram: ram.ok_or(SnapshotError::MissingField("ram"))?,
+            original: original.ok_or(SnapshotError::MissingField("original"))?,
+            input: input.ok_or(SnapshotError::MissingField("input"))?,
+            output: output.ok_or(SnapshotError::MissingField("output"))?,
+        })
+    }
+}
+
+impl Intcode {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            relative_base: self.relative_base,
+            stepped: self.stepped,
+            ram: self.ram.clone(),
+            original: self.original.clone(),
+            input: self.input.clone(),
+            output: self.output.clone(),
+        }
+    }
+    /// Returns the machine to the state in `snap`. Watchpoints are kept, but states seen
+    /// before the restore no longer count towards repeated state detection, and instructions
+    /// executed before it can no longer be undone.
+    pub fn restore(&mut self, snap: &Snapshot) {
+        self.pc = snap.pc;
+        self.relative_base = snap.relative_base;
+        self.stepped = snap.stepped;
+        self.ram.clone_from(&snap.ram);
+        self.original.clone_from(&snap.original);
+        self.input.clone_from(&snap.input);
+        self.output.clone_from(&snap.output);
+        self.prev_states.clear();
+        if let Some(undo) = &mut self.undo {
+            undo.steps.clear();
+        }
+    }
+}
+impl From<&Snapshot> for Intcode {
+    fn from(snap: &Snapshot) -> Intcode {
+        let mut ic = Intcode::default();
+        ic.restore(snap);
+        ic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::RunResult;
+
+    /// Day 09's BOOST program, stopped part way through the self test
+    fn midway() -> Intcode {
+        let mut ic = Intcode::parse(aoch::daystr!("09"));
+        ic.input.push_back(1);
+        for _ in 0..100 {
+            assert_eq!(ic.step::<false>(), None);
+        }
+        ic
+    }
+
+    #[test]
+    fn formats_roundtrip() {
+        let ic = midway();
+        let snap = ic.snapshot();
+        assert_eq!(Snapshot::from_bytes(&snap.to_bytes()), Ok(snap.clone()));
+        assert_eq!(snap.to_string().parse(), Ok(snap.clone()));
+
+        let mut extremes = Snapshot {
+            relative_base: ICInt::MIN,
+            ram: Ram::from(vec![ICInt::MAX, -1, 0, 1, 64, -65]),
+            input: VecDeque::from([ICInt::MIN]),
+            ..Default::default()
+        };
+        extremes.ram.set(1 << 40, -5).unwrap();
+        extremes.ram.set((1 << 40) + 3, ICInt::MAX).unwrap();
+        assert!(extremes.to_string().contains("\nsparse 1099511627776=-5,1099511627779="));
+        assert_eq!(Snapshot::from_bytes(&extremes.to_bytes()), Ok(extremes.clone()));
+        assert_eq!(extremes.to_string().parse(), Ok(extremes));
+    }
+
+    #[test]
+    fn resume_and_branch() {
+        let mut ic = midway();
+        let snap = ic.snapshot();
+        assert_eq!(ic.run(), RunResult::Halted);
+        let finished = ic.output.clone();
+
+        let mut resumed = Intcode::from(&Snapshot::from_bytes(&snap.to_bytes()).unwrap());
+        assert_eq!(resumed.run(), RunResult::Halted);
+        assert_eq!(resumed.output, finished);
+        assert_eq!(resumed.stepped(), ic.stepped());
+
+        // resetting a restored machine still returns to the original program
+        resumed.reset();
+        resumed.input.push_back(2);
+        assert_eq!(resumed.run(), RunResult::Halted);
+        assert_eq!(resumed.output.len(), 1);
+    }
+
+    #[test]
+    fn errors() {
+        let bytes = midway().snapshot().to_bytes();
+        assert_eq!(Snapshot::from_bytes(&bytes[1..]), Err(SnapshotError::BadHeader));
+        assert_eq!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]), Err(SnapshotError::Truncated));
+        let mut long = bytes.clone();
+        long.push(0);
+        assert_eq!(Snapshot::from_bytes(&long), Err(SnapshotError::TrailingBytes));
+        let mut huge = MAGIC.to_vec();
+        huge.extend([0xff; 19]);
+        huge.push(0x7f);
+        assert_eq!(Snapshot::from_bytes(&huge), Err(SnapshotError::Overflow));
+
+        assert_eq!("pc 0".parse::<Snapshot>(), Err(SnapshotError::BadHeader));
+        assert_eq!("intcode snapshot\npc 0\nrb x".parse::<Snapshot>(), Err(SnapshotError::BadLine(3)));
+        assert_eq!("intcode snapshot\npc -1".parse::<Snapshot>(), Err(SnapshotError::BadLine(2)));
+        assert_eq!("intcode snapshot\npc 0\nrb 0".parse::<Snapshot>(), Err(SnapshotError::MissingField("stepped")));
+        assert_eq!("intcode snapshot\nsparse 5=1".parse::<Snapshot>(), Err(SnapshotError::BadLine(2)));
+        assert_eq!("intcode snapshot\nram 1\nsparse 5:1".parse::<Snapshot>(), Err(SnapshotError::BadLine(3)));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let snap = midway().snapshot();
+        let dir = std::env::temp_dir();
+        for name in ["aoc2019-snapshot-test.bin", "aoc2019-snapshot-test.txt"] {
+            let path = dir.join(name);
+            snap.save(&path).unwrap();
+            assert_eq!(Snapshot::load(&path).unwrap(), snap);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Interactive terminal for ASCII Intcode programs.
+//!
+//! Lines typed are sent to the program through an [`AsciiConsole`], and its text is printed
+//! as it arrives. Output values that aren't ASCII are printed on their own line in brackets.
+//!
+//! Lines are read through a [`LineReader`]. On a real terminal that's a [`LineEditor`], which
+//! supports cursor movement, editing within the line and recalling earlier lines with the arrow
+//! keys; any other input is read as [`PlainLines`]. Earlier lines can also be recalled with
+//! `!`-expansions as in a shell, and `:undo` takes back the last line by restoring the machine
+//! to the state it was in before the line was sent - handy for a text adventure. Lines starting
+//! with `:` are commands to the terminal rather than input to the program.
+//!
+//! Everything printed and every line sent can also be logged to a transcript.
+
+use std::io::{self, BufRead, Write};
+
+use rustyline::error::ReadlineError;
+
+use super::ascii::AsciiConsole;
+use super::{Intcode, RunResult};
+
+const HELP: &str = "\
+lines can be edited and recalled with the arrow keys, and are sent to the program as typed,
+except for:
+  !!            repeat the previous line
+  !n            repeat line n of the history
+  !text         repeat the most recent line starting with text
+  :history      list the lines sent so far
+  :undo         take back the last line sent
+  :help         show this message
+  :quit         exit";
+
+/// A source of lines typed by the user
+pub trait LineReader {
+    /// Shows `prompt` and reads the line typed after it, without its line ending. Returns None
+    /// once there are no more lines, such as at the end of a file or when the user hangs up.
+    /// Readers that don't have their own display show the prompt on `out`.
+    fn read_line(&mut self, prompt: &str, out: &mut dyn Write) -> io::Result<Option<String>>;
+    /// Remembers a line that was entered, so that it can be recalled while editing
+    fn add_history(&mut self, _line: &str) {}
+}
+
+/// Reads whole lines from a reader, such as a file or a pipe, without any editing
+pub struct PlainLines<R>(pub R);
+impl<R: BufRead> LineReader for PlainLines<R> {
+    fn read_line(&mut self, prompt: &str, out: &mut dyn Write) -> io::Result<Option<String>> {
+        out.write_all(prompt.as_bytes())?;
+        out.flush()?;
+        let mut line = String::new();
+        if self.0.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let len = line.trim_end_matches(&['\r', '\n'][..]).len();
+        line.truncate(len);
+        Ok(Some(line))
+    }
+}
+
+/// Reads lines from the terminal with a line editor, which draws the prompt itself
+pub struct LineEditor(rustyline::Editor<()>);
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor(rustyline::Editor::new())
+    }
+}
+impl Default for LineEditor {
+    fn default() -> LineEditor {
+        LineEditor::new()
+    }
+}
+impl LineReader for LineEditor {
+    fn read_line(&mut self, prompt: &str, out: &mut dyn Write) -> io::Result<Option<String>> {
+        // anything still buffered has to be shown before the editor takes over the line
+        out.flush()?;
+        match self.0.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => Ok(None),
+            Err(ReadlineError::Io(e)) => Err(e),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+    fn add_history(&mut self, line: &str) {
+        self.0.add_history_entry(line);
+    }
+}
+
+pub struct Terminal {
+    pub console: AsciiConsole,
+    /// Lines sent to the program, after expansion
+    pub history: Vec<String>,
+    /// The machine as it was before each line of the history was sent
+    states: Vec<Intcode>,
+    transcript: Option<Box<dyn Write>>,
+}
+impl Terminal {
+    pub fn new(ic: Intcode) -> Terminal {
+        Terminal {
+            console: AsciiConsole::new(ic),
+            history: Vec::new(),
+            states: Vec::new(),
+            transcript: None,
+        }
+    }
+    /// Logs the session to `transcript`, including the lines sent to the program
+    pub fn with_transcript<T: Write + 'static>(mut self, transcript: T) -> Terminal {
+        self.transcript = Some(Box::new(transcript));
+        self
+    }
+
+    /// Expands a reference to an earlier line, if the line is one
+    fn expand(&self, line: &str) -> Result<String, String> {
+        let found = match line.strip_prefix('!') {
+            None | Some("") => return Ok(line.to_owned()),
+            Some("!") => self.history.last(),
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) => n.checked_sub(1).and_then(|i| self.history.get(i)),
+                Err(_) => self.history.iter().rev().find(|l| l.starts_with(n)),
+            },
+        };
+        found.cloned().ok_or_else(|| format!("{}: no such line in the history", line))
+    }
+
+    /// Prints text to the terminal and transcript
+    fn print<W: Write>(&mut self, out: &mut W, text: &str) -> io::Result<()> {
+        out.write_all(text.as_bytes())?;
+        if let Some(t) = &mut self.transcript {
+            t.write_all(text.as_bytes())?;
+        }
+        Ok(())
+    }
+    /// Prints the lines the program has output. An unfinished line is left to be shown as
+    /// the prompt for the next line typed.
+    fn show<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let mut text = String::new();
+        for line in self.console.take_lines() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        for v in self.console.take_values() {
+            text.push_str(&format!("[{}]\n", v));
+        }
+        self.print(out, &text)
+    }
+
+    /// Runs the program, reading lines from `input` whenever it wants more, until it halts,
+    /// the input runs out or the user quits
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, out: W) -> io::Result<()> {
+        self.repl_with(PlainLines(input), out)
+    }
+    /// Runs the program like [`Terminal::repl`], reading lines through a [`LineReader`]
+    pub fn repl_with<L: LineReader, W: Write>(&mut self, mut input: L, mut out: W) -> io::Result<()> {
+        let mut rr = self.console.run();
+        self.show(&mut out)?;
+        while rr == RunResult::Starved {
+            let prompt = std::mem::take(&mut self.console.partial);
+            if let Some(t) = &mut self.transcript {
+                t.write_all(prompt.as_bytes())?;
+            }
+            let line = match input.read_line(&prompt, &mut out)? {
+                Some(line) => line,
+                None => break,
+            };
+            if !line.trim().is_empty() {
+                input.add_history(&line);
+            }
+            match line.as_str() {
+                ":q" | ":quit" => break,
+                ":help" => writeln!(out, "{}", HELP)?,
+                ":history" => {
+                    for (i, l) in self.history.iter().enumerate() {
+                        writeln!(out, "{:>4}  {}", i + 1, l)?;
+                    }
+                },
+                ":undo" => match (self.states.pop(), self.history.pop()) {
+                    (Some(ic), Some(l)) => {
+                        self.console.ic = ic;
+                        self.print(&mut out, &format!("[took back \"{}\"]\n", l))?;
+                    },
+                    _ => writeln!(out, "[nothing to take back]")?,
+                },
+                cmd if cmd.starts_with(':') => writeln!(out, "unknown command {}, see :help", cmd)?,
+                typed => match self.expand(typed) {
+                    Ok(line) => {
+                        if line != typed {
+                            writeln!(out, "{}", line)?;
+                        }
+                        if let Some(t) = &mut self.transcript {
+                            writeln!(t, "{}", line)?;
+                        }
+                        self.states.push(self.console.ic.clone());
+                        rr = self.console.command(&line);
+                        self.history.push(line);
+                        self.show(&mut out)?;
+                    },
+                    Err(e) => writeln!(out, "{}", e)?,
+                },
+            }
+        }
+        // any prompt that was never answered
+        let rest = std::mem::take(&mut self.console.partial);
+        self.print(&mut out, &rest)?;
+        if !rest.is_empty() && rr != RunResult::Starved {
+            self.print(&mut out, "\n")?;
+        }
+        match rr {
+            RunResult::Starved => {},
+            RunResult::Halted => self.print(&mut out, "[program halted]\n")?,
+            rr => self.print(&mut out, &format!("[program stopped: {:?}]\n", rr))?,
+        }
+        if let Some(t) = &mut self.transcript {
+            t.flush()?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Prompts for lines and echoes them back, then outputs the number of characters read
+    /// after three lines
+    const PROGRAM: &str = "
+    prompt: out #62
+            out #32
+    loop:   inp [c]
+            out [c]
+            eq  [c], #10, [t]
+            jnz [t], #eol
+            add [len], #1, [len]
+            jnz #1, #loop
+    eol:    add [lines], #-1, [lines]
+            jnz [lines], #prompt
+            out [len]
+            hlt
+    c:      db 0
+    t:      db 0
+    len:    db 1000
+    lines:  db 3
+    ";
+
+    /// A transcript that can still be read once the terminal owns it
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn session() {
+        let log = Shared::default();
+        let mut term = Terminal::new(Intcode::assemble(PROGRAM).unwrap()).with_transcript(log.clone());
+        let mut out = Vec::new();
+        term.repl("north\n!!\n:undo\n:history\n!9\n:take\ntake lamp\n!n\n".as_bytes(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            "> north\n",
+            "> north\n",
+            "north\n",
+            "> [took back \"north\"]\n",
+            "   1  north\n",
+            "!9: no such line in the history\n",
+            "unknown command :take, see :help\n",
+            "take lamp\n",
+            "> north\n",
+            "north\n",
+            "[1019]\n",
+            "[program halted]\n",
+        ));
+        assert_eq!(term.history, vec!["north", "take lamp", "north"]);
+        assert_eq!(String::from_utf8(log.0.borrow().clone()).unwrap(), concat!(
+            "> north\n",
+            "north\n",
+            "> north\n",
+            "north\n",
+            "> [took back \"north\"]\n",
+            "take lamp\n",
+            "take lamp\n",
+            "> north\n",
+            "north\n",
+            "[1019]\n",
+            "[program halted]\n",
+        ));
+    }
+}
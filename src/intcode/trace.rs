@@ -0,0 +1,314 @@
+//! Execution traces, for finding where two runs of a program part ways.
+//!
+//! While tracing is enabled with [`Intcode::start_trace`], every instruction that completes
+//! is recorded as a [`TraceStep`]: where it was, the values of the parameters it read, the
+//! memory it wrote and any value it took as input or produced as output. Instructions that
+//! stop the machine - halting, starving or faulting - aren't recorded, since they have no
+//! effect. Traces are saved as text, one step per line:
+//!
+//! ```text
+//! intcode trace
+//! 0 pc=0 op=3 write=9:41 in=41
+//! 1 pc=2 op=1 args=41,1 write=10:42
+//! 2 pc=6 op=4 args=42 out=42
+//! ```
+//!
+//! Comparing two traces with [`Trace::diff`] gives the first step at which they differ, and
+//! [`Trace::replay`] runs a machine on a trace's inputs, checking it against the trace as it goes.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use itertools::{EitherOrBoth, Itertools};
+
+use super::word::Word;
+use super::{ICInt, Intcode, RunConfig, RunResult};
+
+const HEADER: &str = "intcode trace";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IoEvent<W = ICInt> {
+    Input(W),
+    Output(W),
+}
+
+/// Everything a single instruction did
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct TraceStep<W = ICInt> {
+    /// Number of instructions the machine had executed before this one
+    pub step: usize,
+    pub pc: usize,
+    pub op: u8,
+    /// Values of the parameters the instruction read, in order
+    pub operands: Vec<W>,
+    /// Addresses written, with the value written to each
+    pub writes: Vec<(usize, W)>,
+    pub io: Option<IoEvent<W>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Trace<W = ICInt> {
+    pub steps: Vec<TraceStep<W>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceError {
+    /// The text does not start with a trace header
+    BadHeader,
+    /// A line could not be understood
+    BadLine(usize),
+}
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::BadHeader => write!(f, "not an Intcode trace"),
+            TraceError::BadLine(line) => write!(f, "invalid trace line {}", line),
+        }
+    }
+}
+impl std::error::Error for TraceError {}
+
+/// The first difference between two traces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence<W = ICInt> {
+    /// Position of the differing steps within the traces
+    pub index: usize,
+    /// The step from the first trace, or None if it ended first
+    pub expected: Option<TraceStep<W>>,
+    /// The step from the second trace, or None if it ended first
+    pub actual: Option<TraceStep<W>>,
+}
+impl<W: fmt::Display> fmt::Display for Divergence<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn line<W: fmt::Display>(f: &mut fmt::Formatter<'_>, sign: char, step: &Option<TraceStep<W>>) -> fmt::Result {
+            match step {
+                Some(step) => writeln!(f, "{} {}", sign, step),
+                None => writeln!(f, "{} (end of trace)", sign),
+            }
+        }
+        writeln!(f, "traces diverge at step {}", self.index)?;
+        line(f, '-', &self.expected)?;
+        line(f, '+', &self.actual)
+    }
+}
+
+impl<W: Word> Trace<W> {
+    /// Finds the first step at which `other` differs from this trace, including one of them
+    /// ending before the other.
+    pub fn diff(&self, other: &Trace<W>) -> Option<Divergence<W>> {
+        self.steps.iter().zip_longest(&other.steps).enumerate().find_map(|(index, pair)| {
+            let (expected, actual) = match pair {
+                EitherOrBoth::Both(a, b) if a == b => return None,
+                EitherOrBoth::Both(a, b) => (Some(a.clone()), Some(b.clone())),
+                EitherOrBoth::Left(a) => (Some(a.clone()), None),
+                EitherOrBoth::Right(b) => (None, Some(b.clone())),
+            };
+            Some(Divergence { index, expected, actual })
+        })
+    }
+    /// Every value the traced machine read as input, in order
+    pub fn inputs(&self) -> impl Iterator<Item = &W> + '_ {
+        self.steps.iter().filter_map(|s| match &s.io {
+            Some(IoEvent::Input(v)) => Some(v),
+            _ => None,
+        })
+    }
+    /// Runs `ic` from its current state on the inputs recorded in this trace, stopping at the
+    /// first step where it does something different. Any input already queued on `ic` is
+    /// used first, and tracing is left enabled so the new trace can be inspected afterwards.
+    pub fn replay(&self, ic: &mut Intcode<W>) -> Result<RunResult, Divergence<W>> {
+        ic.start_trace();
+        ic.input.extend(self.inputs().cloned());
+        let mut steps = 0;
+        let config = RunConfig { max_steps: Some(1), ..Default::default() };
+        let rr = loop {
+            // one step at a time, so a diverging machine is caught before it can run away
+            match ic.run_with(&config) {
+                RunResult::OutOfSteps => {},
+                rr => break rr,
+            }
+            steps += 1;
+            let recorded = self.steps.get(steps - 1);
+            let actual = ic.trace.as_ref().and_then(|t| t.steps.last());
+            if recorded != actual {
+                return Err(Divergence { index: steps - 1, expected: recorded.cloned(), actual: actual.cloned() });
+            }
+        };
+        match self.steps.get(steps) {
+            Some(step) => Err(Divergence { index: steps, expected: Some(step.clone()), actual: None }),
+            None => Ok(rr),
+        }
+    }
+    /// Writes the trace to `path`, as text
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Trace<W>> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<W: fmt::Display> fmt::Display for TraceStep<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} pc={} op={}", self.step, self.pc, self.op)?;
+        for (i, v) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " args=" } else { "," }, v)?;
+        }
+        for (i, (addr, v)) in self.writes.iter().enumerate() {
+            write!(f, "{}{}:{}", if i == 0 { " write=" } else { "," }, addr, v)?;
+        }
+        match &self.io {
+            Some(IoEvent::Input(v)) => write!(f, " in={}", v),
+            Some(IoEvent::Output(v)) => write!(f, " out={}", v),
+            None => Ok(()),
+        }
+    }
+}
+impl<W: Word> FromStr for TraceStep<W> {
+    type Err = ();
+    fn from_str(s: &str) -> Result<TraceStep<W>, ()> {
+        fn value<T: FromStr>(s: &str) -> Result<T, ()> {
+            s.parse().map_err(|_| ())
+        }
+        let mut fields = s.split_whitespace();
+        let mut step = TraceStep { step: value(fields.next().ok_or(())?)?, ..Default::default() };
+        for field in fields {
+            let (key, v) = field.split_once('=').ok_or(())?;
+            match key {
+                "pc" => step.pc = value(v)?,
+                "op" => step.op = value(v)?,
+                "args" => step.operands = v.split(',').map(value).collect::<Result<_, _>>()?,
+                "write" => {
+                    step.writes = v.split(',')
+                        .map(|w| {
+                            let (addr, v) = w.split_once(':').ok_or(())?;
+                            Ok((value(addr)?, value(v)?))
+                        })
+                        .collect::<Result<_, _>>()?;
+                },
+                "in" => step.io = Some(IoEvent::Input(value(v)?)),
+                "out" => step.io = Some(IoEvent::Output(value(v)?)),
+                _ => return Err(()),
+            }
+        }
+        Ok(step)
+    }
+}
+
+impl<W: fmt::Display> fmt::Display for Trace<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for step in &self.steps {
+            writeln!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+impl<W: Word> FromStr for Trace<W> {
+    type Err = TraceError;
+    fn from_str(s: &str) -> Result<Trace<W>, TraceError> {
+        let mut lines = s.lines().map(str::trim).enumerate().filter(|(_, l)| !l.is_empty());
+        if lines.next().map(|(_, l)| l) != Some(HEADER) {
+            return Err(TraceError::BadHeader);
+        }
+        let steps = lines
+            .map(|(i, line)| line.parse().map_err(|_| TraceError::BadLine(i + 1)))
+            .collect::<Result<_, _>>()?;
+        Ok(Trace { steps })
+    }
+}
+
+impl<W: Word> Intcode<W> {
+    /// Starts recording a new trace, discarding any previous one
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+    /// Stops tracing, returning everything recorded since tracing started
+    pub fn take_trace(&mut self) -> Option<Trace<W>> {
+        self.trace.take()
+    }
+    pub fn trace(&self) -> Option<&Trace<W>> {
+        self.trace.as_ref()
+    }
+    /// The step being recorded for the current instruction, if tracing
+    #[inline]
+    pub(super) fn trace_step(&mut self) -> Option<&mut TraceStep<W>> {
+        self.trace.as_mut().and_then(|t| t.steps.last_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_plus_one() -> Intcode {
+        Intcode::assemble("
+            inp [x]
+            add [x], #1, [y]
+            out [y]
+            hlt
+            x: db 0
+            y: db 0
+        ").unwrap()
+    }
+
+    #[test]
+    fn record_and_roundtrip() {
+        let mut ic = echo_plus_one();
+        ic.start_trace();
+        ic.input.push_back(41);
+        assert_eq!(ic.run(), RunResult::Halted);
+        let trace = ic.take_trace().unwrap();
+        assert_eq!(trace.steps, vec![
+            TraceStep { step: 0, pc: 0, op: 3, operands: vec![], writes: vec![(9, 41)], io: Some(IoEvent::Input(41)) },
+            TraceStep { step: 1, pc: 2, op: 1, operands: vec![41, 1], writes: vec![(10, 42)], io: None },
+            TraceStep { step: 2, pc: 6, op: 4, operands: vec![42], writes: vec![], io: Some(IoEvent::Output(42)) },
+        ]);
+
+        let text = trace.to_string();
+        assert_eq!(text.lines().nth(2), Some("1 pc=2 op=1 args=41,1 write=10:42"));
+        assert_eq!(text.parse(), Ok(trace));
+        assert_eq!("intcode trace\n0 pc=0 op=3\n1 pc=2 bogus=1".parse::<Trace>(), Err(TraceError::BadLine(3)));
+        assert_eq!("0 pc=0 op=3".parse::<Trace>(), Err(TraceError::BadHeader));
+    }
+
+    #[test]
+    fn starving_isnt_recorded() {
+        let mut ic = echo_plus_one();
+        ic.start_trace();
+        assert_eq!(ic.run(), RunResult::Starved);
+        assert_eq!(ic.trace().unwrap().steps, vec![]);
+    }
+
+    #[test]
+    fn diff_and_replay() {
+        let mut ic = echo_plus_one();
+        ic.start_trace();
+        ic.input.push_back(7);
+        assert_eq!(ic.run(), RunResult::Halted);
+        let trace = ic.take_trace().unwrap();
+        assert_eq!(trace.diff(&trace), None);
+
+        ic.reset();
+        assert_eq!(trace.replay(&mut ic), Ok(RunResult::Halted));
+        assert_eq!(ic.output, vec![8]);
+
+        // adding 2 rather than 1 shows up at the add, not just in the output
+        let mut changed = echo_plus_one();
+        changed.ram[4] = 2;
+        let div = trace.replay(&mut changed).unwrap_err();
+        assert_eq!(div.index, 1);
+        assert_eq!(div.actual.unwrap().writes, vec![(10, 9)]);
+
+        let mut short = trace.clone();
+        short.steps.pop();
+        let div = trace.diff(&short).unwrap();
+        assert!(div.to_string().ends_with("+ (end of trace)\n"));
+        assert_eq!((div.index, div.actual), (2, None));
+    }
+}
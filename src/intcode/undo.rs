@@ -0,0 +1,173 @@
+//! Reverse execution.
+//!
+//! Once enabled with [`Intcode::record_undo`], every instruction that completes logs what it
+//! overwrote - memory, the program counter and relative base - along with any input it
+//! consumed or output it produced. [`Intcode::step_back`] replays that log backwards, one
+//! instruction at a time. Input read while stepping back is returned to the front of
+//! [`Intcode::input`], even if it originally came from an [`IntcodeIo`](super::io::IntcodeIo),
+//! but output written to an `IntcodeIo` can't be taken back.
+
+use std::collections::VecDeque;
+
+use super::word::Word;
+use super::Intcode;
+
+/// Default number of instructions that can be undone
+pub const DEFAULT_UNDO_LIMIT: usize = 1 << 20;
+
+/// What is needed to undo a single instruction
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(super) struct UndoStep<W> {
+    pub(super) pc: usize,
+    pub(super) relative_base: W,
+    /// Addresses written, with the values they held before, in the order they were written
+    pub(super) writes: Vec<(usize, W)>,
+    pub(super) input: Option<W>,
+    /// Whether a value was appended to the machine's own output queue
+    pub(super) output: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(super) struct UndoLog<W> {
+    pub(super) steps: VecDeque<UndoStep<W>>,
+    limit: usize,
+}
+impl<W> UndoLog<W> {
+    /// Called once an instruction completes, forgetting the oldest one if the log is full
+    pub(super) fn complete(&mut self) {
+        if self.steps.len() > self.limit {
+            self.steps.pop_front();
+        }
+    }
+}
+
+impl<W: Word> Intcode<W> {
+    /// Starts logging executed instructions so that up to `limit` of them can be undone
+    pub fn record_undo(&mut self, limit: usize) {
+        self.undo = Some(UndoLog { steps: VecDeque::new(), limit });
+    }
+    /// Stops logging instructions, forgetting any that could have been undone
+    pub fn stop_undo(&mut self) {
+        self.undo = None;
+    }
+    /// Number of instructions that can currently be undone
+    pub fn undo_depth(&self) -> usize {
+        self.undo.as_ref().map_or(0, |u| u.steps.len())
+    }
+
+    /// Undoes the last instruction executed, returning false if there was nothing to undo.
+    /// If the machine is being traced, the instruction is removed from the trace too.
+    pub fn step_back(&mut self) -> bool {
+        let step = match self.undo.as_mut().and_then(|u| u.steps.pop_back()) {
+            Some(step) => step,
+            None => return false,
+        };
+        for (addr, old) in step.writes.into_iter().rev() {
+            self.ram[addr] = old;
+        }
+        if let Some(v) = step.input {
+            self.input.push_front(v);
+        }
+        if step.output {
+            self.output.pop_back();
+        }
+        self.pc = step.pc;
+        self.relative_base = step.relative_base;
+        self.stepped -= 1;
+        if let Some(trace) = &mut self.trace {
+            trace.steps.pop();
+        }
+        true
+    }
+    /// Steps back until the instruction at `pc` is about to be executed again, such as to
+    /// find the last time a loop started. Always steps back at least once. Returns false,
+    /// leaving the machine as it was, if that instruction isn't within the logged history.
+    pub fn run_back_to(&mut self, pc: usize) -> bool {
+        let logged = self.undo.as_ref().map_or(false, |u| u.steps.iter().any(|s| s.pc == pc));
+        if !logged {
+            return false;
+        }
+        while self.step_back() {
+            if self.pc == pc {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The undo information being recorded for the current instruction, if enabled
+    #[inline]
+    pub(super) fn undo_step(&mut self) -> Option<&mut UndoStep<W>> {
+        self.undo.as_mut().and_then(|u| u.steps.back_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::RunResult;
+
+    /// Counts down from its input, outputting each number
+    const COUNTDOWN: &str = "
+            inp [n]
+    loop:   out [n]
+            add [n], #-1, [n]
+            jnz [n], #loop
+            hlt
+    n:      db 0
+    ";
+
+    #[test]
+    fn back_to_the_start() {
+        let mut ic = Intcode::assemble(COUNTDOWN).unwrap();
+        let start = ic.clone();
+        ic.record_undo(DEFAULT_UNDO_LIMIT);
+        ic.input.push_back(3);
+        assert_eq!(ic.run(), RunResult::Halted);
+        assert_eq!(ic.output, vec![3, 2, 1]);
+        assert_eq!(ic.undo_depth(), 10);
+
+        while ic.step_back() {}
+        assert_eq!(ic.input, vec![3], "input is put back");
+        assert!(ic.output.is_empty());
+        assert_eq!((ic.pc, ic.stepped, &ic.ram), (start.pc, start.stepped, &start.ram));
+
+        // the same run happens again
+        assert_eq!(ic.run(), RunResult::Halted);
+        assert_eq!(ic.output, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn run_back_to_loop() {
+        let mut ic = Intcode::assemble(COUNTDOWN).unwrap();
+        ic.record_undo(DEFAULT_UNDO_LIMIT);
+        ic.input.push_back(3);
+        assert_eq!(ic.run(), RunResult::Halted);
+
+        // the last pass around the loop printed 1
+        assert!(ic.run_back_to(2));
+        assert_eq!((ic.pc, ic.ram[12]), (2, 1));
+        assert_eq!(ic.output, vec![3, 2]);
+        assert!(ic.run_back_to(2));
+        assert_eq!(ic.ram[12], 2);
+        let before = ic.clone();
+        assert!(!ic.run_back_to(9), "never ran");
+        assert_eq!(ic, before, "machine is left where it was");
+    }
+
+    #[test]
+    fn limited_history() {
+        let mut ic = Intcode::assemble(COUNTDOWN).unwrap();
+        ic.record_undo(4);
+        ic.input.push_back(3);
+        assert_eq!(ic.run(), RunResult::Halted);
+        assert_eq!(ic.undo_depth(), 4);
+        assert!(!ic.run_back_to(0), "forgotten");
+        assert_eq!((ic.pc, ic.stepped, ic.undo_depth()), (11, 10, 4));
+        assert!(ic.run_back_to(8));
+        assert_eq!((ic.pc, ic.stepped), (8, 9));
+
+        ic.stop_undo();
+        assert!(!ic.step_back());
+    }
+}
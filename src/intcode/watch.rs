@@ -0,0 +1,136 @@
+//! Memory watchpoints.
+//!
+//! Once an address is watched, every matching access made while resolving instruction
+//! parameters is appended to [`Intcode::watch_log`], along with the PC of the instruction
+//! that made it. Reading the parameter words of an instruction counts as a `Fetch` - this
+//! is how values such as day 02's noun and verb are consumed, as addresses.
+
+use std::fmt;
+
+use super::word::Word;
+use super::{ICInt, Intcode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// A parameter word was read to find the address it refers to
+    Fetch,
+    Read,
+    Write,
+}
+
+/// Which kinds of access to an address are reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Watch {
+    /// Report reads and fetches
+    pub read: bool,
+    /// Report writes
+    pub write: bool,
+}
+impl Watch {
+    pub const READ: Watch = Watch { read: true, write: false };
+    pub const WRITE: Watch = Watch { read: false, write: true };
+    pub const ALL: Watch = Watch { read: true, write: true };
+
+    pub fn triggers(&self, kind: Access) -> bool {
+        match kind {
+            Access::Fetch | Access::Read => self.read,
+            Access::Write => self.write,
+        }
+    }
+}
+
+/// A single access to a watched address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemAccess<W = ICInt> {
+    /// Address of the instruction that made the access
+    pub pc: usize,
+    pub addr: usize,
+    pub kind: Access,
+    /// The value read, or the value written
+    pub value: W,
+}
+impl<W: fmt::Display> fmt::Display for MemAccess<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            Access::Fetch => "fetch",
+            Access::Read => "read",
+            Access::Write => "write",
+        };
+        write!(f, "PC={:04} {:<5} [{}] = {}", self.pc, kind, self.addr, self.value)
+    }
+}
+
+impl<W: Word> Intcode<W> {
+    /// Watches `addr` for the given kinds of access, replacing any existing watch on it
+    pub fn watch(&mut self, addr: usize, watch: Watch) {
+        if watch == Watch::default() {
+            self.watchpoints.remove(&addr);
+        } else {
+            self.watchpoints.insert(addr, watch);
+        }
+    }
+    pub fn unwatch(&mut self, addr: usize) {
+        self.watchpoints.remove(&addr);
+    }
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, Watch)> + '_ {
+        self.watchpoints.iter().map(|(&a, &w)| (a, w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::RunResult;
+
+    #[test]
+    fn reads_and_writes() {
+        let mut ic = Intcode::assemble("
+            inp [x]
+            add [x], #1, [y]
+            out rb+10
+            hlt
+            x: db 0
+            y: db 0
+        ").unwrap();
+        ic.watch(9, Watch::WRITE);
+        ic.watch(10, Watch::ALL);
+        ic.input.push_back(41);
+        assert_eq!(ic.run(), RunResult::Halted);
+        assert_eq!(ic.output, vec![42]);
+        assert_eq!(ic.watch_log, vec![
+            MemAccess { pc: 0, addr: 9, kind: Access::Write, value: 41 },
+            MemAccess { pc: 2, addr: 10, kind: Access::Write, value: 42 },
+            MemAccess { pc: 6, addr: 10, kind: Access::Read, value: 42 },
+        ]);
+        assert_eq!(ic.watch_log[1].to_string(), "PC=0002 write [10] = 42");
+
+        ic.reset();
+        assert!(ic.watch_log.is_empty());
+        ic.unwatch(10);
+        ic.input.push_back(1);
+        assert_eq!(ic.run(), RunResult::Halted);
+        assert_eq!(ic.watch_log.len(), 1);
+        assert_eq!(ic.watchpoints().collect::<Vec<_>>(), vec![(9, Watch::WRITE)]);
+    }
+
+    #[test]
+    fn noun_and_verb() {
+        // day 02's noun and verb are the parameters of the first instruction. With a verb
+        // of 2, the verb's parameter points at itself, so it is read rather than fetched.
+        let mut ic = Intcode::parse(aoch::daystr!("02"));
+        ic.ram[1] = 12;
+        ic.ram[2] = 2;
+        ic.watch(0, Watch::WRITE);
+        ic.watch(1, Watch::READ);
+        ic.watch(2, Watch::READ);
+        assert_eq!(ic.run(), RunResult::Halted);
+
+        let log = &ic.watch_log;
+        assert_eq!(&log[..2], &[
+            MemAccess { pc: 0, addr: 1, kind: Access::Fetch, value: 12 },
+            MemAccess { pc: 0, addr: 2, kind: Access::Read, value: 2 },
+        ]);
+        let last = log.last().unwrap();
+        assert_eq!((last.addr, last.kind, last.value), (0, Access::Write, 11590668));
+    }
+}
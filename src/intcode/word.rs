@@ -0,0 +1,193 @@
+//! Word types that Intcode machines can compute with.
+//!
+//! Most programs fit comfortably in an `i64`, which is the fastest choice. Plain integer words
+//! wrap on overflow, as the hardware does; wrapping them in [`Checked`] instead stops the
+//! machine with [`Fault::Overflow`](super::Fault::Overflow). [`BigInt`] never overflows, at
+//! the cost of an allocation for most arithmetic.
+
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use num::{BigInt, Signed, ToPrimitive, Zero};
+
+use super::ICInt;
+
+pub trait Word: Clone + Default + Eq + Ord + Hash + fmt::Debug + fmt::Display + FromStr + 'static {
+    fn from_i64(v: i64) -> Self;
+    /// Converts to an address, if the word is non-negative and small enough
+    fn to_usize(&self) -> Option<usize>;
+    /// Converts to the default word type for diagnostics, saturating if it doesn't fit
+    fn to_icint(&self) -> ICInt;
+    fn is_zero(&self) -> bool;
+    fn is_negative(&self) -> bool;
+    /// Adds two words, returning None if the machine should fault due to overflow
+    fn try_add(&self, rhs: &Self) -> Option<Self>;
+    /// Multiplies two words, returning None if the machine should fault due to overflow
+    fn try_mul(&self, rhs: &Self) -> Option<Self>;
+}
+
+/// A primitive integer word whose arithmetic reports overflow, rather than wrapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Checked<T>(pub T);
+impl<T: fmt::Display> fmt::Display for Checked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+impl<T: FromStr> FromStr for Checked<T> {
+    type Err = T::Err;
+    fn from_str(s: &str) -> Result<Checked<T>, T::Err> {
+        s.parse().map(Checked)
+    }
+}
+
+macro_rules! primitive_word {
+    ($($t:ty),*) => {$(
+        impl Word for $t {
+            #[inline]
+            fn from_i64(v: i64) -> $t {
+                v as $t
+            }
+            #[inline]
+            fn to_usize(&self) -> Option<usize> {
+                usize::try_from(*self).ok()
+            }
+            #[inline]
+            fn to_icint(&self) -> ICInt {
+                *self as ICInt
+            }
+            #[inline]
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+            #[inline]
+            fn is_negative(&self) -> bool {
+                *self < 0
+            }
+            #[inline]
+            fn try_add(&self, rhs: &$t) -> Option<$t> {
+                Some(self.wrapping_add(*rhs))
+            }
+            #[inline]
+            fn try_mul(&self, rhs: &$t) -> Option<$t> {
+                Some(self.wrapping_mul(*rhs))
+            }
+        }
+        impl Word for Checked<$t> {
+            #[inline]
+            fn from_i64(v: i64) -> Checked<$t> {
+                Checked(v as $t)
+            }
+            #[inline]
+            fn to_usize(&self) -> Option<usize> {
+                usize::try_from(self.0).ok()
+            }
+            #[inline]
+            fn to_icint(&self) -> ICInt {
+                self.0 as ICInt
+            }
+            #[inline]
+            fn is_zero(&self) -> bool {
+                self.0 == 0
+            }
+            #[inline]
+            fn is_negative(&self) -> bool {
+                self.0 < 0
+            }
+            #[inline]
+            fn try_add(&self, rhs: &Checked<$t>) -> Option<Checked<$t>> {
+                self.0.checked_add(rhs.0).map(Checked)
+            }
+            #[inline]
+            fn try_mul(&self, rhs: &Checked<$t>) -> Option<Checked<$t>> {
+                self.0.checked_mul(rhs.0).map(Checked)
+            }
+        }
+    )*};
+}
+primitive_word!(i64, i128);
+
+impl Word for BigInt {
+    fn from_i64(v: i64) -> BigInt {
+        BigInt::from(v)
+    }
+    fn to_usize(&self) -> Option<usize> {
+        ToPrimitive::to_usize(self)
+    }
+    fn to_icint(&self) -> ICInt {
+        self.to_i128().unwrap_or(if Signed::is_negative(self) { ICInt::MIN } else { ICInt::MAX })
+    }
+    fn is_zero(&self) -> bool {
+        Zero::is_zero(self)
+    }
+    fn is_negative(&self) -> bool {
+        Signed::is_negative(self)
+    }
+    fn try_add(&self, rhs: &BigInt) -> Option<BigInt> {
+        Some(self + rhs)
+    }
+    fn try_mul(&self, rhs: &BigInt) -> Option<BigInt> {
+        Some(self * rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Fault, Intcode, RunResult};
+
+    /// Squares its input forever, outputting each result
+    const SQUARES: &str = "3,11,2,11,11,11,4,11,1105,1,2,0";
+
+    fn squares<W: Word>(start: i64, count: usize) -> (Vec<W>, RunResult) {
+        let mut ic = Intcode::<W>::parse_words(SQUARES);
+        ic.input.push_back(W::from_i64(start));
+        let config = crate::intcode::RunConfig { max_steps: Some(3 * count + 1), ..Default::default() };
+        let rr = ic.run_with(&config);
+        (ic.output.into_iter().collect(), rr)
+    }
+
+    #[test]
+    fn boost_on_every_word() {
+        fn boost<W: Word>(mode: i64) -> W {
+            let mut ic = Intcode::<W>::parse_words(aoch::daystr!("09"));
+            ic.input.push_back(W::from_i64(mode));
+            assert_eq!(ic.run(), RunResult::Halted);
+            assert_eq!(ic.output.len(), 1);
+            ic.output.pop_front().unwrap()
+        }
+        assert_eq!(boost::<i64>(1), 3512778005);
+        assert_eq!(boost::<Checked<i64>>(1), Checked(3512778005));
+        assert_eq!(boost::<Checked<i128>>(2), Checked(boost::<i128>(2)));
+        assert_eq!(boost::<BigInt>(1), BigInt::from(3512778005i64));
+    }
+
+    #[test]
+    fn overflow() {
+        // 3^64 doesn't fit in an i64, and 3^128 doesn't fit in an i128
+        let (wrapped, _) = squares::<i64>(3, 6);
+        assert_eq!(wrapped.last(), Some(&3i64.wrapping_pow(64)));
+
+        let (checked, rr) = squares::<Checked<i64>>(3, 6);
+        assert_eq!(checked, vec![Checked(9), Checked(81), Checked(6561), Checked(43046721), Checked(1853020188851841)]);
+        assert_eq!(rr, RunResult::Fault(Fault::Overflow { pc: 2 }));
+        let (_, rr) = squares::<Checked<i128>>(3, 7);
+        assert_eq!(rr, RunResult::Fault(Fault::Overflow { pc: 2 }));
+
+        let (exact, rr) = squares::<BigInt>(3, 7);
+        assert_eq!(rr, RunResult::OutOfSteps);
+        assert_eq!(exact.last(), Some(&BigInt::from(3).pow(128)));
+        assert_eq!(exact.last().unwrap().to_icint(), ICInt::MAX);
+    }
+
+    #[test]
+    fn huge_addresses() {
+        let jump = |target: &str| {
+            let mut ic = Intcode::<BigInt>::parse_words(&format!("1105,1,{}", target));
+            ic.run()
+        };
+        assert_eq!(jump("-99999999999999999999999999999999999999999999"), RunResult::Fault(Fault::NegativeJump { pc: 0, target: ICInt::MIN }));
+        assert_eq!(jump("99999999999999999999999999999999999999999999"), RunResult::Fault(Fault::Overflow { pc: 0 }));
+    }
+}
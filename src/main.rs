@@ -4,7 +4,7 @@
 #![feature(array_windows)]
 
 use aoch::DayPart;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 mod intcode;
 mod rendering;
@@ -13,6 +13,9 @@ aoch::load_days!("../input");
 
 #[derive(Parser, Debug)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Day to run. If not supplied, all are ran.
     #[clap(short, long, value_parser(1..=25))]
     day: Option<i64>,
@@ -30,9 +33,224 @@ struct Args {
     quiet: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints a disassembly listing of a day's Intcode program
+    Disasm {
+        /// Day whose input is disassembled
+        #[clap(value_parser(1..=25))]
+        day: i64,
+    },
+    /// Prints the control-flow graph of a day's Intcode program in Graphviz DOT format
+    Cfg {
+        /// Day whose input is analysed
+        #[clap(value_parser(1..=25))]
+        day: i64,
+    },
+    /// Decompiles a day's Intcode program into structured pseudo-code
+    Decompile {
+        /// Day whose input is decompiled
+        #[clap(value_parser(1..=25))]
+        day: i64,
+    },
+    /// Starts an interactive debugger on a day's Intcode program
+    Debug {
+        /// Day whose input is debugged
+        #[clap(value_parser(1..=25))]
+        day: i64,
+    },
+    /// Connects a day's ASCII Intcode program to the terminal, to play or probe it by hand.
+    /// Lines can be edited and recalled with the arrow keys, and taken back with `:undo`.
+    Play {
+        /// Day whose input is run
+        #[clap(value_parser(1..=25))]
+        day: i64,
+        /// File to log the session to
+        #[clap(long)]
+        transcript: Option<std::path::PathBuf>,
+    },
+    /// Plays day 13's arcade game, animating the screen in the terminal
+    Arcade {
+        /// Milliseconds to pause after each frame
+        #[clap(long, default_value_t = 20)]
+        delay: u64,
+    },
+    /// Assembles an Intcode source file, printing the comma-separated program
+    Asm {
+        /// Path to the assembly source
+        path: std::path::PathBuf,
+    },
+    /// Times the Intcode interpreter on day 02 part 2 and day 09 part 2, with and without
+    /// the decoded instruction cache
+    Bench {
+        /// Number of times each puzzle is solved
+        #[clap(short = 'n', long, default_value_t = 10)]
+        iterations: u32,
+    },
+    /// Runs a day's Intcode program with the given input, then prints a profile of which
+    /// instructions ran and how often
+    Profile {
+        /// Day whose input is profiled
+        #[clap(value_parser(1..=25))]
+        day: i64,
+        /// Values to give the program as input
+        #[clap(allow_hyphen_values = true)]
+        input: Vec<intcode::ICInt>,
+    },
+    /// Runs a day's Intcode program with the given input, saving a trace of every step for
+    /// `trace-diff` to compare
+    Trace {
+        /// Day whose input is traced
+        #[clap(value_parser(1..=25))]
+        day: i64,
+        /// File the trace is saved to
+        out: std::path::PathBuf,
+        /// Values to give the program as input
+        #[clap(allow_hyphen_values = true)]
+        input: Vec<intcode::ICInt>,
+    },
+    /// Compares two saved Intcode execution traces, printing the first step where they differ
+    TraceDiff {
+        /// Trace from the known good run
+        expected: std::path::PathBuf,
+        /// Trace from the run being checked
+        actual: std::path::PathBuf,
+    },
+}
+
+/// Parses the input of an Intcode day
+fn intcode_input(day: i64) -> intcode::Intcode {
+    let (inp, _) = RUNNERS[day as usize - 1];
+    intcode::Intcode::parse(inp)
+}
+
+fn bench(iterations: u32) {
+    use aoch::AoCDay;
+    type Solver = fn(&mut intcode::Intcode) -> intcode::ICInt;
+
+    // decoding every instruction and counting hits per PC, as runs used to
+    let uncached = intcode::RunConfig {
+        decode_cache: false,
+        max_hits_per_pc: Some(10_000_000),
+        ..Default::default()
+    };
+    let cached = intcode::RunConfig { max_hits_per_pc: None, ..Default::default() };
+
+    let puzzles: [(&str, i64, Solver); 2] = [
+        ("day 02 part 2", 2, |ic| days::day02::Day02.part2(ic)),
+        ("day 09 part 2", 9, |ic| days::day09::Day09.part2(ic)),
+    ];
+    for (name, day, solve) in puzzles {
+        let mut ic = intcode_input(day);
+        // the solvers run with whatever limits the machine was given
+        let mut time = |config| {
+            ic.run_config = config;
+            let start = std::time::Instant::now();
+            let answer = solve(&mut ic);
+            for _ in 1..iterations {
+                assert_eq!(solve(&mut ic), answer);
+            }
+            (start.elapsed() / iterations.max(1), answer)
+        };
+        let (before, expected) = time(uncached);
+        let (after, answer) = time(cached);
+        assert_eq!(answer, expected, "{} gave a different answer with the cache", name);
+        println!("{}: {:>10.2?} uncached, {:>10.2?} cached ({:.2}x)",
+            name, before, after, before.as_secs_f64() / after.as_secs_f64());
+    }
+}
+
 fn main() {
     let args = Args::parse();
 
+    match args.command {
+        Some(Command::Disasm { day }) => {
+            print!("{}", intcode_input(day).disassemble());
+            return;
+        },
+        Some(Command::Cfg { day }) => {
+            print!("{}", intcode_input(day).cfg().to_dot());
+            return;
+        },
+        Some(Command::Decompile { day }) => {
+            print!("{}", intcode_input(day).decompile());
+            return;
+        },
+        Some(Command::Debug { day }) => {
+            let mut dbg = intcode::debugger::Debugger::new(intcode_input(day));
+            dbg.repl(std::io::stdin().lock(), std::io::stdout()).expect("unable to use terminal");
+            return;
+        },
+        Some(Command::Play { day, transcript }) => {
+            let mut term = intcode::terminal::Terminal::new(intcode_input(day));
+            if let Some(path) = transcript {
+                let file = std::fs::File::create(&path)
+                    .unwrap_or_else(|e| panic!("unable to create {}: {}", path.display(), e));
+                term = term.with_transcript(std::io::BufWriter::new(file));
+            }
+            term.repl_with(intcode::terminal::LineEditor::new(), std::io::stdout())
+                .expect("unable to use terminal");
+            return;
+        },
+        Some(Command::Arcade { delay }) => {
+            let mut prog = intcode_input(13);
+            // insert quarters
+            prog.ram[0] = 2;
+            let mut arcade = days::day13::Arcade::default()
+                .with_animation(std::io::stdout(), std::time::Duration::from_millis(delay));
+            arcade.play(&mut prog).expect("game stopped unexpectedly");
+            return;
+        },
+        Some(Command::Bench { iterations }) => {
+            bench(iterations);
+            return;
+        },
+        Some(Command::Profile { day, input }) => {
+            let mut ic = intcode_input(day);
+            ic.start_profile();
+            ic.input.extend(input);
+            let rr = ic.run();
+            println!("day {:02} stopped: {:?}, with {} outputs\n", day, rr, ic.output.len());
+            print!("{}", ic.profile_report().unwrap());
+            return;
+        },
+        Some(Command::Trace { day, out, input }) => {
+            let mut ic = intcode_input(day);
+            ic.start_trace();
+            ic.input.extend(input);
+            let rr = ic.run();
+            let trace = ic.take_trace().unwrap();
+            trace.save(&out)
+                .unwrap_or_else(|e| panic!("unable to write {}: {}", out.display(), e));
+            println!("day {:02} stopped: {:?}, with {} outputs; saved {} steps to {}",
+                day, rr, ic.output.len(), trace.steps.len(), out.display());
+            return;
+        },
+        Some(Command::TraceDiff { expected, actual }) => {
+            let load = |path: &std::path::Path| intcode::trace::Trace::<intcode::ICInt>::load(path)
+                .unwrap_or_else(|e| panic!("unable to read {}: {}", path.display(), e));
+            let (expected, actual) = (load(&expected), load(&actual));
+            match expected.diff(&actual) {
+                Some(div) => print!("{}", div),
+                None => println!("traces are identical ({} steps)", expected.steps.len()),
+            }
+            return;
+        },
+        Some(Command::Asm { path }) => {
+            let src = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("unable to read {}: {}", path.display(), e));
+            match intcode::asm::assemble(&src) {
+                Ok(prog) => println!("{}", prog.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(",")),
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    std::process::exit(1);
+                },
+            }
+            return;
+        },
+        None => {},
+    }
+
     let day = args.day.map(|n| n as usize);
     let part = args.part.map(|n| match n {
         1 => DayPart::Part1,